@@ -0,0 +1,783 @@
+//! Client for the spotify web api.
+//!
+//! spotify_web only covers reading the currently playing item and resuming and pausing playback,
+//! without a device or any of the other endpoints spotr needs, and it does not expose error
+//! bodies. Rather than extending it per endpoint, spotr talks to the api with attohttpc here and
+//! keeps using spotify_web for the authorization code exchange and token refresh only.
+
+use std::cell::RefCell;
+use std::time::Duration;
+
+use anyhow::Result;
+use attohttpc::{Method, Response, StatusCode};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::de::DeserializeOwned;
+
+use crate::error::ApplicationError;
+use crate::model;
+
+const API_URL: &str = "https://api.spotify.com/v1";
+
+/// Maximum number of items the api accepts in a single request.
+const MAX_BATCH: usize = 100;
+
+/// Delay before the first retry of a failed request, doubled for every following attempt.
+const BACKOFF_BASE_MS: u64 = 500;
+
+/// Upper bound of the delay between attempts.
+const BACKOFF_MAX_MS: u64 = 30_000;
+
+/// Longest `Retry-After` that is waited out, beyond it the rate limit error is returned instead.
+const MAX_RETRY_AFTER_SECS: u64 = 60;
+
+/// Renews the access token after spotify rejected it, returning the new access token.
+pub type Refresh = Box<dyn FnMut() -> Result<String>>;
+
+/// Thin client for the parts of the spotify web api that spotr uses.
+pub struct Spotify {
+    access_token: RefCell<String>,
+    refresh: RefCell<Option<Refresh>>,
+    max_attempts: u32,
+}
+
+impl Spotify {
+    pub fn new(access_token: impl Into<String>) -> Self {
+        Self {
+            access_token: RefCell::new(access_token.into()),
+            refresh: RefCell::new(None),
+            max_attempts: 1,
+        }
+    }
+
+    /// Sends requests up to `max_attempts` times when they are rate limited or fail with a server
+    /// or network error.
+    pub fn with_max_attempts(self, max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            ..self
+        }
+    }
+
+    /// Renews the access token with `refresh` and retries once when a request is unauthorized.
+    pub fn with_refresh(self, refresh: Refresh) -> Self {
+        Self {
+            refresh: RefCell::new(Some(refresh)),
+            ..self
+        }
+    }
+
+    fn call(
+        &self,
+        method: Method,
+        path: &str,
+        params: &[(&str, &str)],
+        body: Option<serde_json::Value>,
+    ) -> Result<Response> {
+        let mut response = self.send_retrying(&method, path, params, body.as_ref())?;
+
+        if response.status() == StatusCode::UNAUTHORIZED {
+            if let Some(refresh) = self.refresh.borrow_mut().as_mut() {
+                log::debug!("access token was rejected, refreshing it and retrying");
+
+                *self.access_token.borrow_mut() = refresh()?;
+                response = self.send_retrying(&method, path, params, body.as_ref())?;
+            }
+        }
+
+        if response.is_success() {
+            Ok(response)
+        } else {
+            Err(error_from(response).into())
+        }
+    }
+
+    /// Sends a request, retrying it while it is rate limited or fails transiently. Server and
+    /// network errors are only retried for idempotent methods, as spotify may have carried out
+    /// the request before failing, while rate limited requests were never carried out.
+    fn send_retrying(
+        &self,
+        method: &Method,
+        path: &str,
+        params: &[(&str, &str)],
+        body: Option<&serde_json::Value>,
+    ) -> Result<Response> {
+        let mut attempt = 1;
+        let idempotent = matches!(*method, Method::GET | Method::PUT | Method::DELETE);
+
+        loop {
+            let result = self.send(method.clone(), path, params, body);
+
+            let delay = match &result {
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    retry_after(response).unwrap_or_else(|| Some(backoff(attempt)))
+                }
+                Ok(response) if idempotent && response.status().is_server_error() => {
+                    Some(backoff(attempt))
+                }
+                Err(err) if idempotent && is_network_error(err) => Some(backoff(attempt)),
+                _ => None,
+            };
+
+            match delay {
+                Some(delay) if attempt < self.max_attempts => {
+                    log::debug!(
+                        "attempt {} of {} {} failed with {}, retrying in {:?}",
+                        attempt,
+                        method,
+                        path,
+                        match &result {
+                            Ok(response) => response.status().to_string(),
+                            Err(err) => err.to_string(),
+                        },
+                        delay
+                    );
+
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                _ => {
+                    return result.map_err(|err| {
+                        if is_network_error(&err) {
+                            ApplicationError::Network(err.to_string()).into()
+                        } else {
+                            err
+                        }
+                    })
+                }
+            }
+        }
+    }
+
+    fn send(
+        &self,
+        method: Method,
+        path: &str,
+        params: &[(&str, &str)],
+        body: Option<&serde_json::Value>,
+    ) -> Result<Response> {
+        log::trace!("{} {}{} {:?}", method, API_URL, path, params);
+
+        let may_have_body = method != Method::GET;
+
+        let request = attohttpc::RequestBuilder::new(method, format!("{}{}", API_URL, path))
+            .bearer_auth(self.access_token.borrow().as_str())
+            .params(params);
+
+        Ok(match body {
+            Some(body) => request
+                .header(attohttpc::header::CONTENT_TYPE, "application/json")
+                .text(serde_json::to_string(body)?)
+                .send()?,
+            // attohttpc only sets the length of non-empty bodies, and spotify refuses requests
+            // that may have a body but have no length with 411
+            None if may_have_body => request
+                .header(attohttpc::header::CONTENT_LENGTH, "0")
+                .send()?,
+            None => request.send()?,
+        })
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str, params: &[(&str, &str)]) -> Result<T> {
+        let bytes = self.call(Method::GET, path, params, None)?.bytes()?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    pub fn devices(&self) -> Result<Vec<model::Device>> {
+        Ok(self
+            .get::<model::Devices>("/me/player/devices", &[])?
+            .devices)
+    }
+
+    pub fn transfer_playback(&self, device_id: &str, play: bool) -> Result<()> {
+        self.call(
+            Method::PUT,
+            "/me/player",
+            &[],
+            Some(serde_json::json!({ "device_ids": [device_id], "play": play })),
+        )?;
+
+        Ok(())
+    }
+
+    /// Current playback state, `None` if nothing is playing.
+    pub fn playback_state(&self) -> Result<Option<model::PlaybackState>> {
+        let response = self.call(
+            Method::GET,
+            "/me/player",
+            &[("additional_types", "track,episode")],
+            None,
+        )?;
+
+        if response.status() == StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::from_slice(&response.bytes()?)?))
+    }
+
+    pub fn resume_playback(&self, device_id: Option<&str>) -> Result<()> {
+        self.call(Method::PUT, "/me/player/play", &device(device_id), None)?;
+        Ok(())
+    }
+
+    /// Starts playback of `uri`, for contexts optionally at the track with index `offset`.
+    pub fn start_playback(
+        &self,
+        uri: &model::SpotifyUri,
+        offset: Option<u32>,
+        position_ms: Option<u64>,
+        device_id: Option<&str>,
+    ) -> Result<()> {
+        let mut body = if uri.kind.is_context() {
+            serde_json::json!({ "context_uri": uri.to_string() })
+        } else {
+            serde_json::json!({ "uris": [uri.to_string()] })
+        };
+
+        if let Some(offset) = offset {
+            body["offset"] = serde_json::json!({ "position": offset });
+        }
+
+        if let Some(position) = position_ms {
+            body["position_ms"] = position.into();
+        }
+
+        self.call(
+            Method::PUT,
+            "/me/player/play",
+            &device(device_id),
+            Some(body),
+        )?;
+        Ok(())
+    }
+
+    pub fn queue(&self) -> Result<model::Queue> {
+        self.get("/me/player/queue", &[])
+    }
+
+    pub fn add_to_queue(&self, uri: &model::SpotifyUri, device_id: Option<&str>) -> Result<()> {
+        let uri = uri.to_string();
+
+        self.call(
+            Method::POST,
+            "/me/player/queue",
+            &with_device(device_id, &[("uri", &uri)]),
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    /// Saved items of the kind at `path`, e.g. `/me/tracks`.
+    pub fn saved<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        limit: u32,
+        offset: u32,
+    ) -> Result<model::Paging<model::Saved<T>>> {
+        self.get(
+            path,
+            &[
+                ("limit", &limit.to_string()),
+                ("offset", &offset.to_string()),
+            ],
+        )
+    }
+
+    pub fn save(&self, uri: &model::SpotifyUri) -> Result<()> {
+        self.call(
+            Method::PUT,
+            library_path(uri.kind)?,
+            &[("ids", &uri.id)],
+            None,
+        )?;
+        Ok(())
+    }
+
+    pub fn unsave(&self, uri: &model::SpotifyUri) -> Result<()> {
+        self.call(
+            Method::DELETE,
+            library_path(uri.kind)?,
+            &[("ids", &uri.id)],
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn is_saved(&self, uri: &model::SpotifyUri) -> Result<bool> {
+        let path = format!("{}/contains", library_path(uri.kind)?);
+        let saved: Vec<bool> = self.get(&path, &[("ids", &uri.id)])?;
+
+        Ok(saved.first().copied().unwrap_or(false))
+    }
+
+    pub fn current_user(&self) -> Result<model::PrivateUser> {
+        self.get("/me", &[])
+    }
+
+    pub fn playlists(
+        &self,
+        limit: u32,
+        offset: u32,
+    ) -> Result<model::Paging<model::SimplePlaylist>> {
+        self.get(
+            "/me/playlists",
+            &[
+                ("limit", &limit.to_string()),
+                ("offset", &offset.to_string()),
+            ],
+        )
+    }
+
+    /// All playlists owned or followed by the user.
+    pub fn all_playlists(&self) -> Result<Vec<model::SimplePlaylist>> {
+        let mut playlists = Vec::new();
+
+        loop {
+            let page = self.playlists(50, playlists.len() as u32)?;
+            playlists.extend(page.items);
+
+            if page.next.is_none() {
+                return Ok(playlists);
+            }
+        }
+    }
+
+    pub fn playlist_items(
+        &self,
+        id: &str,
+        limit: u32,
+        offset: u32,
+    ) -> Result<model::Paging<model::PlaylistItem>> {
+        self.get(
+            &format!("/playlists/{}/tracks", id),
+            &[
+                ("limit", &limit.to_string()),
+                ("offset", &offset.to_string()),
+                ("additional_types", "track,episode"),
+            ],
+        )
+    }
+
+    pub fn create_playlist(
+        &self,
+        user_id: &str,
+        name: &str,
+        public: bool,
+        description: Option<&str>,
+    ) -> Result<model::SimplePlaylist> {
+        let mut body = serde_json::json!({ "name": name, "public": public });
+
+        if let Some(description) = description {
+            body["description"] = description.into();
+        }
+
+        let response = self.call(
+            Method::POST,
+            &format!("/users/{}/playlists", user_id),
+            &[],
+            Some(body),
+        )?;
+
+        Ok(serde_json::from_slice(&response.bytes()?)?)
+    }
+
+    /// Adds `uris` to the playlist at `position`, or at the end, in batches of at most 100.
+    pub fn add_to_playlist(&self, id: &str, uris: &[String], position: Option<u32>) -> Result<()> {
+        let path = format!("/playlists/{}/tracks", id);
+
+        for (i, batch) in uris.chunks(MAX_BATCH).enumerate() {
+            let mut body = serde_json::json!({ "uris": batch });
+
+            if let Some(position) = position {
+                body["position"] = (position + (i * MAX_BATCH) as u32).into();
+            }
+
+            self.call(Method::POST, &path, &[], Some(body))?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes all occurrences of `uris` from the playlist, in batches of at most 100.
+    pub fn remove_from_playlist(&self, id: &str, uris: &[String]) -> Result<()> {
+        let path = format!("/playlists/{}/tracks", id);
+
+        for batch in uris.chunks(MAX_BATCH) {
+            let tracks: Vec<_> = batch
+                .iter()
+                .map(|uri| serde_json::json!({ "uri": uri }))
+                .collect();
+
+            self.call(
+                Method::DELETE,
+                &path,
+                &[],
+                Some(serde_json::json!({ "tracks": tracks })),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves `length` items starting at `start` to before the item at `insert_before`.
+    pub fn reorder_playlist(
+        &self,
+        id: &str,
+        start: u32,
+        length: u32,
+        insert_before: u32,
+    ) -> Result<()> {
+        self.call(
+            Method::PUT,
+            &format!("/playlists/{}/tracks", id),
+            &[],
+            Some(serde_json::json!({
+                "range_start": start,
+                "range_length": length,
+                "insert_before": insert_before,
+            })),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn rename_playlist(&self, id: &str, name: &str) -> Result<()> {
+        self.call(
+            Method::PUT,
+            &format!("/playlists/{}", id),
+            &[],
+            Some(serde_json::json!({ "name": name })),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn follow_playlist(&self, id: &str) -> Result<()> {
+        self.call(
+            Method::PUT,
+            &format!("/playlists/{}/followers", id),
+            &[],
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn unfollow_playlist(&self, id: &str) -> Result<()> {
+        self.call(
+            Method::DELETE,
+            &format!("/playlists/{}/followers", id),
+            &[],
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    /// Recently played tracks, most recent first, optionally only those played after `after`.
+    pub fn recently_played(
+        &self,
+        limit: u32,
+        after: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Vec<model::PlayHistory>> {
+        let limit = limit.to_string();
+        let after = after.map(|after| after.timestamp_millis().to_string());
+
+        let mut params = vec![("limit", limit.as_str())];
+        params.extend(after.as_deref().map(|after| ("after", after)));
+
+        Ok(self
+            .get::<model::CursorPaging<_>>("/me/player/recently-played", &params)?
+            .items)
+    }
+
+    /// The user's top items of `kind`, either `tracks` or `artists`.
+    pub fn top<T: DeserializeOwned>(
+        &self,
+        kind: &str,
+        range: &str,
+        limit: u32,
+        offset: u32,
+    ) -> Result<model::Paging<T>> {
+        self.get(
+            &format!("/me/top/{}", kind),
+            &[
+                ("time_range", range),
+                ("limit", &limit.to_string()),
+                ("offset", &offset.to_string()),
+            ],
+        )
+    }
+
+    pub fn search(
+        &self,
+        query: &str,
+        types: &[model::ItemType],
+        limit: u32,
+        offset: u32,
+        market: Option<&str>,
+    ) -> Result<model::SearchResults> {
+        let types = types
+            .iter()
+            .map(|t| t.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let limit = limit.to_string();
+        let offset = offset.to_string();
+
+        let mut params = vec![
+            ("q", query),
+            ("type", &types),
+            ("limit", &limit),
+            ("offset", &offset),
+        ];
+
+        params.extend(market.map(|market| ("market", market)));
+
+        self.get("/search", &params)
+    }
+
+    pub fn pause_playback(&self, device_id: Option<&str>) -> Result<()> {
+        self.call(Method::PUT, "/me/player/pause", &device(device_id), None)?;
+        Ok(())
+    }
+
+    pub fn next(&self, device_id: Option<&str>) -> Result<()> {
+        self.call(Method::POST, "/me/player/next", &device(device_id), None)?;
+        Ok(())
+    }
+
+    pub fn previous(&self, device_id: Option<&str>) -> Result<()> {
+        self.call(
+            Method::POST,
+            "/me/player/previous",
+            &device(device_id),
+            None,
+        )?;
+        Ok(())
+    }
+
+    pub fn seek(&self, position_ms: u64, device_id: Option<&str>) -> Result<()> {
+        let position = position_ms.to_string();
+
+        self.call(
+            Method::PUT,
+            "/me/player/seek",
+            &with_device(device_id, &[("position_ms", &position)]),
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn volume(&self, percent: u32, device_id: Option<&str>) -> Result<()> {
+        let percent = percent.to_string();
+
+        self.call(
+            Method::PUT,
+            "/me/player/volume",
+            &with_device(device_id, &[("volume_percent", &percent)]),
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn shuffle(&self, state: bool, device_id: Option<&str>) -> Result<()> {
+        let state = if state { "true" } else { "false" };
+
+        self.call(
+            Method::PUT,
+            "/me/player/shuffle",
+            &with_device(device_id, &[("state", state)]),
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn repeat(&self, state: &str, device_id: Option<&str>) -> Result<()> {
+        self.call(
+            Method::PUT,
+            "/me/player/repeat",
+            &with_device(device_id, &[("state", state)]),
+            None,
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Path of the user's library of items of `kind`.
+pub fn library_path(kind: model::ItemType) -> Result<&'static str> {
+    match kind {
+        model::ItemType::Track => Ok("/me/tracks"),
+        model::ItemType::Album => Ok("/me/albums"),
+        model::ItemType::Show => Ok("/me/shows"),
+        model::ItemType::Episode => Ok("/me/episodes"),
+        kind => Err(anyhow::anyhow!(
+            "Items of type {} can not be saved to the library",
+            kind.as_str()
+        )),
+    }
+}
+
+fn device(device_id: Option<&str>) -> Vec<(&str, &str)> {
+    with_device(device_id, &[])
+}
+
+fn with_device<'a>(
+    device_id: Option<&'a str>,
+    params: &[(&'a str, &'a str)],
+) -> Vec<(&'a str, &'a str)> {
+    params
+        .iter()
+        .cloned()
+        .chain(device_id.map(|id| ("device_id", id)))
+        .collect()
+}
+
+/// Delay requested by a rate limited response, `Some(None)` if it is too long to wait out.
+fn retry_after(response: &Response) -> Option<Option<Duration>> {
+    let secs = retry_after_secs(response)?;
+
+    if secs > MAX_RETRY_AFTER_SECS {
+        log::debug!("rate limited for {} seconds, not waiting", secs);
+        Some(None)
+    } else {
+        Some(Some(Duration::from_secs(secs)))
+    }
+}
+
+fn retry_after_secs(response: &Response) -> Option<u64> {
+    response
+        .headers()
+        .get(attohttpc::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Exponential backoff with jitter, so parallel invocations do not retry in lockstep.
+fn backoff(attempt: u32) -> Duration {
+    let max = BACKOFF_BASE_MS
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(BACKOFF_MAX_MS);
+
+    let mut bytes = [0; 8];
+    let random = SystemRandom::new()
+        .fill(&mut bytes)
+        .map(|_| u64::from_le_bytes(bytes))
+        .unwrap_or(0);
+
+    Duration::from_millis(max / 2 + random % (max / 2 + 1))
+}
+
+fn is_network_error(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<attohttpc::Error>()
+            .map(attohttpc::Error::kind),
+        Some(attohttpc::ErrorKind::Io(_))
+    )
+}
+
+fn error_from(response: Response) -> ApplicationError {
+    let status = response.status();
+    let retry_after = retry_after_secs(&response);
+
+    let error = response
+        .bytes()
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<model::ErrorResponse>(&bytes).ok())
+        .map(|body| body.error);
+
+    let reason = error.as_ref().and_then(|error| error.reason.as_deref());
+
+    let message = error
+        .as_ref()
+        .map(|error| error.message.clone())
+        .unwrap_or_else(|| {
+            status
+                .canonical_reason()
+                .unwrap_or("Unknown error")
+                .to_owned()
+        });
+
+    match (status, reason) {
+        (_, Some("NO_ACTIVE_DEVICE")) => ApplicationError::NoActiveDevice,
+        (_, Some("PREMIUM_REQUIRED")) => ApplicationError::PremiumRequired(message),
+        (StatusCode::UNAUTHORIZED, _) => ApplicationError::Unauthorized(message),
+        (StatusCode::NOT_FOUND, _) => ApplicationError::NotFound(message),
+        (StatusCode::TOO_MANY_REQUESTS, _) => ApplicationError::RateLimited(retry_after),
+        _ => ApplicationError::SpotifyApi {
+            status: status.as_u16(),
+            message,
+        },
+    }
+}
+
+/// Finds the item referred to by `query`, either by (a prefix of) its id or by its name.
+fn resolve<'a, T>(
+    items: &'a [T],
+    query: &str,
+    what: &'static str,
+    id: impl Fn(&T) -> Option<&str>,
+    name: impl Fn(&T) -> &str,
+) -> Result<&'a T, ApplicationError> {
+    let by_id: Vec<_> = items
+        .iter()
+        .filter(|item| id(item).is_some_and(|id| id.starts_with(query)))
+        .collect();
+
+    let matches = if by_id.is_empty() {
+        let exact: Vec<_> = items
+            .iter()
+            .filter(|item| name(item).eq_ignore_ascii_case(query))
+            .collect();
+
+        if exact.is_empty() {
+            let query = query.to_lowercase();
+
+            items
+                .iter()
+                .filter(|item| name(item).to_lowercase().starts_with(&query))
+                .collect()
+        } else {
+            exact
+        }
+    } else {
+        by_id
+    };
+
+    match matches.as_slice() {
+        [] => Err(ApplicationError::NoMatch(what, query.to_owned())),
+        [item] => Ok(item),
+        many => Err(ApplicationError::Ambiguous(
+            what,
+            query.to_owned(),
+            many.iter()
+                .map(|item| name(item))
+                .collect::<Vec<_>>()
+                .join(", "),
+        )),
+    }
+}
+
+pub fn resolve_device<'a>(
+    devices: &'a [model::Device],
+    query: &str,
+) -> Result<&'a model::Device, ApplicationError> {
+    resolve(devices, query, "device", |d| d.id.as_deref(), |d| &d.name)
+}
+
+pub fn resolve_playlist<'a>(
+    playlists: &'a [model::SimplePlaylist],
+    query: &str,
+) -> Result<&'a model::SimplePlaylist, ApplicationError> {
+    resolve(playlists, query, "playlist", |p| Some(&p.id), |p| &p.name)
+}
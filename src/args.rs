@@ -0,0 +1,215 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use chrono::TimeZone;
+
+/// Position to seek to, given as `1:23`, `83s`, `+10s`, `-30s` or `50%`.
+#[derive(Debug, Clone, Copy)]
+pub enum SeekTarget {
+    Absolute(u64),
+    Relative(i64),
+    Percent(u64),
+}
+
+impl SeekTarget {
+    /// Resolves the target to an absolute position given the current progress and duration.
+    pub fn position_ms(self, progress_ms: u64, duration_ms: u64) -> u64 {
+        let position = match self {
+            Self::Absolute(ms) => ms,
+            Self::Relative(delta) if delta < 0 => progress_ms.saturating_sub(-delta as u64),
+            Self::Relative(delta) => progress_ms + delta as u64,
+            Self::Percent(percent) => duration_ms * percent / 100,
+        };
+
+        if duration_ms > 0 {
+            position.min(duration_ms)
+        } else {
+            position
+        }
+    }
+}
+
+/// Parses `83`, `83s`, `1:23` or `1:01:23` into milliseconds.
+pub fn parse_time(s: &str) -> Result<u64> {
+    let s = s.trim_end_matches('s');
+
+    let mut secs = 0;
+    let mut parts = 0;
+
+    for part in s.split(':') {
+        parts += 1;
+        secs = secs * 60
+            + part
+                .parse::<u64>()
+                .map_err(|_| anyhow!("Invalid time '{}', expected e.g. 83s, 1:23 or 1:01:23", s))?;
+    }
+
+    anyhow::ensure!(parts <= 3, "Invalid time '{}', too many components", s);
+
+    Ok(secs * 1000)
+}
+
+impl FromStr for SeekTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(percent) = s.strip_suffix('%') {
+            let percent = percent
+                .parse::<u64>()
+                .map_err(|_| anyhow!("Invalid percentage '{}'", s))?;
+
+            anyhow::ensure!(percent <= 100, "Percentage must be at most 100%");
+
+            Ok(Self::Percent(percent))
+        } else if let Some(delta) = s.strip_prefix('+') {
+            Ok(Self::Relative(parse_time(delta)? as i64))
+        } else if let Some(delta) = s.strip_prefix('-') {
+            Ok(Self::Relative(-(parse_time(delta)? as i64)))
+        } else {
+            Ok(Self::Absolute(parse_time(s)?))
+        }
+    }
+}
+
+/// Volume given as `0` to `100`, or relative to the current volume as `+5` or `-5`.
+#[derive(Debug, Clone, Copy)]
+pub enum VolumeTarget {
+    Absolute(u32),
+    Relative(i32),
+}
+
+impl VolumeTarget {
+    pub fn percent(self, current: u32) -> u32 {
+        match self {
+            Self::Absolute(volume) => volume,
+            Self::Relative(delta) => (current as i32 + delta).clamp(0, 100) as u32,
+        }
+    }
+}
+
+impl FromStr for VolumeTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid volume '{}', expected 0-100, +N or -N", s);
+
+        if s.starts_with('+') || s.starts_with('-') {
+            Ok(Self::Relative(s.parse().map_err(|_| invalid())?))
+        } else {
+            let volume = s.parse().map_err(|_| invalid())?;
+
+            anyhow::ensure!(volume <= 100, "Volume must be between 0 and 100");
+
+            Ok(Self::Absolute(volume))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Switch {
+    On,
+    Off,
+    Toggle,
+}
+
+impl Switch {
+    pub fn apply(self, current: bool) -> bool {
+        match self {
+            Self::On => true,
+            Self::Off => false,
+            Self::Toggle => !current,
+        }
+    }
+}
+
+impl FromStr for Switch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "on" => Ok(Self::On),
+            "off" => Ok(Self::Off),
+            "toggle" => Ok(Self::Toggle),
+            _ => Err(anyhow!("Expected on, off or toggle, got '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RepeatMode {
+    Off,
+    Track,
+    Context,
+}
+
+impl RepeatMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Track => "track",
+            Self::Context => "context",
+        }
+    }
+}
+
+impl FromStr for RepeatMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "off" => Ok(Self::Off),
+            "track" => Ok(Self::Track),
+            "context" => Ok(Self::Context),
+            _ => Err(anyhow!("Expected off, track or context, got '{}'", s)),
+        }
+    }
+}
+
+/// Period over which top items are computed.
+#[derive(Debug, Clone, Copy)]
+pub enum TimeRange {
+    Short,
+    Medium,
+    Long,
+}
+
+impl TimeRange {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Short => "short_term",
+            Self::Medium => "medium_term",
+            Self::Long => "long_term",
+        }
+    }
+}
+
+impl FromStr for TimeRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "short" => Ok(Self::Short),
+            "medium" => Ok(Self::Medium),
+            "long" => Ok(Self::Long),
+            _ => Err(anyhow!("Expected short, medium or long, got '{}'", s)),
+        }
+    }
+}
+
+/// Parses an RFC 3339 timestamp or a `YYYY-MM-DD` date, the latter at midnight UTC.
+pub fn parse_datetime(s: &str) -> Result<chrono::DateTime<chrono::Utc>> {
+    if let Ok(datetime) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Ok(datetime.with_timezone(&chrono::Utc));
+    }
+
+    let date = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| {
+        anyhow!(
+            "Invalid date '{}', expected e.g. 2020-01-31 or 2020-01-31T12:00:00Z",
+            s
+        )
+    })?;
+
+    let midnight = date.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+
+    Ok(chrono::Utc.from_utc_datetime(&midnight))
+}
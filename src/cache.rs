@@ -0,0 +1,40 @@
+//! Results of the last search, so that later invocations can refer to them by index.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+use anyhow::Result;
+
+use crate::error::ApplicationError;
+use crate::model::ItemSummary;
+use crate::output::SearchRecord;
+
+const SEARCH_FILE: &str = "search.json";
+
+pub fn store(results: &[SearchRecord]) -> Result<()> {
+    let mut path = crate::config::data_dir()?;
+    path.push(SEARCH_FILE);
+
+    log::debug!("caching {} search results in {:#?}", results.len(), path);
+
+    serde_json::to_writer(BufWriter::new(File::create(&path)?), results)?;
+
+    Ok(())
+}
+
+pub fn lookup(index: u32) -> Result<ItemSummary> {
+    let mut path = crate::config::data_dir()?;
+    path.push(SEARCH_FILE);
+
+    let results: Vec<SearchRecord> = match File::open(&path) {
+        Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    results
+        .into_iter()
+        .find(|result| result.index == index)
+        .map(|result| result.item)
+        .ok_or_else(|| ApplicationError::NoSuchResult(index).into())
+}
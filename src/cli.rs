@@ -1,30 +1,94 @@
-use std::io::Write;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 use anyhow::{anyhow, Result};
-use spotify_web::Spotify;
+use ring::aead::LessSafeKey;
 use structopt::StructOpt;
 
+use crate::api::Spotify;
 use crate::config::Config;
+use crate::error::ApplicationError;
+use crate::model::{ItemType, SpotifyUri};
+use crate::output::{self, Format};
 use crate::{Scope, Token};
 
+/// Token refreshed while running a command, written to the config once the command is done.
+type Refreshed = Rc<RefCell<Option<Token>>>;
+
 struct LazySpotify {
-    generator: fn(Option<String>, &mut Config) -> Result<Spotify<Scope>>,
-    cell: Option<std::result::Result<Spotify<Scope>, crate::error::ArcAnyhowError>>,
+    generator: fn(&LazySpotify, &mut Config) -> Result<Spotify>,
+    cell: Option<std::result::Result<Spotify, crate::error::ArcAnyhowError>>,
     client_id: Option<String>,
+    device: Option<String>,
+    auth: AuthOptions,
+
+    /// Scopes the command needs
+    scopes: &'static [&'static str],
+
+    refreshed: Refreshed,
+    max_attempts: u32,
 }
 
 impl LazySpotify {
-    fn as_mut<'a>(&mut self, cfg: &'a mut Config) -> Result<&mut Spotify<Scope>, anyhow::Error> {
-        let id = &mut self.client_id;
-        let generator = self.generator;
+    fn as_mut(&mut self, cfg: &mut Config) -> Result<&mut Spotify, anyhow::Error> {
+        if self.cell.is_none() {
+            let spotify = (self.generator)(self, cfg).map_err(crate::error::ArcAnyhowError::new);
+            self.cell = Some(spotify);
+        }
 
         self.cell
-            .get_or_insert_with(|| {
-                (generator)(id.take(), cfg).map_err(crate::error::ArcAnyhowError::new)
-            })
+            .as_mut()
+            .expect("is_some")
             .as_mut()
             .map_err(Into::into)
     }
+
+    /// Authorizes from scratch for `scopes` on top of the ones already granted, replacing the
+    /// client's token, and uses the new token from then on.
+    fn login(&mut self, cfg: &mut Config, scopes: &[&str]) -> Result<&mut Spotify> {
+        let id = self.client_id(cfg)?.clone();
+
+        self.refreshed.borrow_mut().take();
+        let spotify = CLI::login(&id, self, scopes, cfg)?;
+
+        self.cell = Some(Ok(spotify));
+        self.as_mut(cfg)
+    }
+
+    /// Writes a token that was refreshed while running the command to the config.
+    fn save_refreshed(&self, cfg: &mut Config) -> Result<()> {
+        if let Some(token) = self.refreshed.borrow_mut().take() {
+            let id = self.client_id(cfg)?.clone();
+            let enc_key = crate::keyring::get_or_create_key()?;
+
+            cfg.set_token(&id, &token, &enc_key)?;
+        }
+
+        Ok(())
+    }
+
+    fn client_id<'a>(&'a self, cfg: &'a Config) -> Result<&'a String> {
+        self.client_id
+            .as_ref()
+            .or_else(|| cfg.default())
+            .ok_or(anyhow!("Client id required!"))
+    }
+
+    /// Id of the device to target, either given on the command line or the client's default.
+    fn device_id(&mut self, cfg: &mut Config) -> Result<Option<String>> {
+        if let Some(query) = self.device.clone() {
+            let devices = self.as_mut(cfg)?.devices()?;
+            let device = crate::api::resolve_device(&devices, &query)?;
+
+            Ok(device.id.clone())
+        } else {
+            Ok(self
+                .client_id(cfg)
+                .ok()
+                .and_then(|id| cfg.default_device(id))
+                .cloned())
+        }
+    }
 }
 
 #[derive(StructOpt)]
@@ -32,12 +96,20 @@ impl LazySpotify {
     rename_all = "kebab-case",
     about = env!("CARGO_PKG_DESCRIPTION"),
     author = env!("CARGO_PKG_AUTHORS"),
+    after_help = crate::error::EXIT_CODES_HELP,
 )]
 pub struct CLI {
     /// Client id of the spotify application to use
     #[structopt(long, short = "i")]
     pub client_id: Option<String>,
 
+    /// Name or id prefix of the device to control, overrides the client's default device
+    #[structopt(long, short = "d")]
+    pub device: Option<String>,
+
+    #[structopt(long, short = "o", help = output::format_help())]
+    pub output: Option<Format>,
+
     /// Verbosity of logging, repeated occurrences count as higher log levels
     #[structopt(
         name = "verbose",
@@ -47,11 +119,65 @@ pub struct CLI {
     )]
     pub verbose: u8,
 
+    /// Maximum number of attempts for requests that are rate limited, or fail with a server or
+    /// network error and can safely be sent again
+    #[structopt(long, default_value = "4")]
+    max_attempts: u32,
+
+    #[structopt(flatten)]
+    auth: AuthOptions,
+
     #[structopt(subcommand)]
     cmd: Command,
 }
 
+/// Options for authorizing with spotify when the client has no valid token
 #[derive(StructOpt)]
+struct AuthOptions {
+    /// Seconds to wait for authorization in the browser before giving up
+    #[structopt(long = "auth-timeout", default_value = "300")]
+    auth_timeout: u64,
+
+    /// Only listen for the authorization redirect on this port, one of the client's redirect uris
+    /// must use it
+    #[structopt(long = "redirect-port")]
+    redirect_port: Option<u16>,
+
+    /// Authorize by pasting the url the browser is redirected to instead of listening for the
+    /// redirect, for when the browser runs on another machine
+    #[structopt(long = "no-browser")]
+    no_browser: bool,
+}
+
+impl AuthOptions {
+    /// Redirect uris of the client to try in order, narrowed down by `--redirect-port`.
+    fn redirect_uris(&self, id: &str, config: &Config) -> Result<Vec<String>> {
+        let mut redirect_uris = match config.redirect_uris(id) {
+            [] => vec![crate::oauth::DEFAULT_REDIRECT_URI.to_owned()],
+            redirect_uris => redirect_uris.to_vec(),
+        };
+
+        if let Some(port) = self.redirect_port.or_else(|| config.redirect_port()) {
+            redirect_uris.retain(|uri| {
+                url::Url::parse(uri)
+                    .ok()
+                    .and_then(|url| url.port_or_known_default())
+                    == Some(port)
+            });
+
+            anyhow::ensure!(
+                !redirect_uris.is_empty(),
+                "No redirect uri of client '{}' uses port {}, add one with 'spotr client redirect'",
+                id,
+                port
+            );
+        }
+
+        Ok(redirect_uris)
+    }
+}
+
+#[derive(StructOpt, Clone)]
 enum Command {
     #[structopt(alias = "c")]
     Client {
@@ -59,15 +185,92 @@ enum Command {
         cmd: Client,
     },
 
+    Login(Login),
+    Logout(Logout),
+    Whoami(Whoami),
+
+    #[structopt(alias = "d")]
+    Device {
+        #[structopt(subcommand)]
+        cmd: Device,
+    },
+
     #[structopt(alias = "s")]
     Status(Status),
 
+    #[structopt(alias = "f")]
+    Search(Search),
+
+    #[structopt(alias = "q")]
+    Queue {
+        #[structopt(subcommand)]
+        cmd: Queue,
+    },
+
+    #[structopt(alias = "save")]
+    Like(Like),
+    Unlike(Unlike),
+
+    #[structopt(alias = "lib")]
+    Library {
+        #[structopt(subcommand)]
+        cmd: Library,
+    },
+
+    #[structopt(alias = "pl")]
+    Playlist {
+        #[structopt(subcommand)]
+        cmd: Playlist,
+    },
+
+    #[structopt(alias = "h")]
+    History(History),
+
+    Top {
+        #[structopt(subcommand)]
+        cmd: Top,
+    },
+
     Play(Play),
     Pause(Pause),
+    Toggle(Toggle),
+
+    #[structopt(alias = "n")]
+    Next(Next),
+
+    #[structopt(alias = "p")]
+    Prev(Prev),
+
+    Seek(Seek),
+
+    #[structopt(alias = "vol")]
+    Volume(Volume),
+
+    Shuffle(Shuffle),
+    Repeat(Repeat),
+}
+
+/// Authorizes the client again, replacing its token
+#[derive(StructOpt, Clone)]
+struct Login {
+    /// Additional scope to request on top of the ones spotr needs, e.g. user-read-email
+    #[structopt(long = "scope", short = "s")]
+    scopes: Vec<String>,
 }
 
+/// Forgets the client's token
+///
+/// The token can not be revoked through the api, access for the client can be revoked
+/// completely at https://www.spotify.com/account/apps/
+#[derive(StructOpt, Clone)]
+struct Logout {}
+
+/// Shows the account the client is authorized for
+#[derive(StructOpt, Clone)]
+struct Whoami {}
+
 /// Edit available clients
-#[derive(StructOpt)]
+#[derive(StructOpt, Clone)]
 enum Client {
     #[structopt(alias = "n")]
     New(ClientNew),
@@ -83,139 +286,1148 @@ enum Client {
 
     #[structopt(alias = "d")]
     Default(ClientDefault),
+
+    #[structopt(alias = "r")]
+    Redirect(ClientRedirect),
+}
+
+/// Inspect and select playback devices
+#[derive(StructOpt, Clone)]
+enum Device {
+    #[structopt(alias = "l")]
+    List(DeviceList),
+
+    #[structopt(alias = "t")]
+    Transfer(DeviceTransfer),
+
+    #[structopt(alias = "d")]
+    Default(DeviceDefault),
+}
+
+/// List available devices
+#[derive(StructOpt, Clone)]
+struct DeviceList {}
+
+/// Transfer playback to another device
+#[derive(StructOpt, Clone)]
+struct DeviceTransfer {
+    /// Name or id prefix of the target device
+    device: String,
+
+    /// Start playback on the new device, by default the current playback state is kept
+    #[structopt(long, short = "p")]
+    play: bool,
+}
+
+/// Set the default device of the current client
+#[derive(StructOpt, Clone)]
+struct DeviceDefault {
+    /// Name or id prefix of the new default device, clears the default if omitted
+    device: Option<String>,
+}
+
+/// Inspect and add to the playback queue
+#[derive(StructOpt, Clone)]
+enum Queue {
+    #[structopt(alias = "a")]
+    Add(QueueAdd),
+
+    #[structopt(alias = "l")]
+    List(QueueList),
+}
+
+/// Add a track or episode to the end of the queue
+#[derive(StructOpt, Clone)]
+struct QueueAdd {
+    /// Spotify uri or url, the number of a result from the last search, or a search query whose
+    /// best match is queued
+    #[structopt(required = true)]
+    query: Vec<String>,
+
+    /// Search for episodes instead of tracks
+    #[structopt(long, short = "e")]
+    episode: bool,
+}
+
+/// List the currently playing item followed by the queued items
+#[derive(StructOpt, Clone)]
+struct QueueList {}
+
+/// Saves an item to the library, the currently playing item by default
+#[derive(StructOpt, Clone)]
+struct Like {
+    /// Spotify uri or url, or the number of a result from the last search
+    item: Option<String>,
+}
+
+/// Removes an item from the library, the currently playing item by default
+#[derive(StructOpt, Clone)]
+struct Unlike {
+    /// Spotify uri or url, or the number of a result from the last search
+    item: Option<String>,
+}
+
+/// Browse the library of saved items
+#[derive(StructOpt, Clone)]
+enum Library {
+    #[structopt(alias = "t")]
+    Tracks(LibraryList),
+
+    #[structopt(alias = "a")]
+    Albums(LibraryList),
+
+    #[structopt(alias = "s")]
+    Shows(LibraryList),
+
+    #[structopt(alias = "c")]
+    Contains(LibraryContains),
+}
+
+/// List saved items, most recently saved first
+#[derive(StructOpt, Clone)]
+struct LibraryList {
+    #[structopt(flatten)]
+    pages: Pages,
+}
+
+#[derive(StructOpt, Clone)]
+struct Pages {
+    /// Maximum number of items, at most 50
+    #[structopt(long, short = "l", default_value = "20")]
+    limit: u32,
+
+    /// Index of the first item
+    #[structopt(long, default_value = "0")]
+    offset: u32,
+
+    /// List all items from the offset onwards
+    #[structopt(long, conflicts_with = "limit")]
+    all: bool,
+}
+
+/// Checks whether an item is saved in the library
+#[derive(StructOpt, Clone)]
+struct LibraryContains {
+    /// Spotify uri or url, or the number of a result from the last search
+    item: String,
+}
+
+/// Manage playlists
+#[derive(StructOpt, Clone)]
+enum Playlist {
+    #[structopt(alias = "l")]
+    List(PlaylistList),
+
+    #[structopt(alias = "s")]
+    Show(PlaylistShow),
+
+    #[structopt(alias = "c")]
+    Create(PlaylistCreate),
+
+    #[structopt(alias = "a")]
+    Add(PlaylistAdd),
+
+    #[structopt(alias = "rm")]
+    Remove(PlaylistRemove),
+
+    #[structopt(alias = "mv")]
+    Reorder(PlaylistReorder),
+
+    Rename(PlaylistRename),
+    Follow(PlaylistFollow),
+    Unfollow(PlaylistUnfollow),
+}
+
+/// List playlists owned or followed by the user
+#[derive(StructOpt, Clone)]
+struct PlaylistList {
+    #[structopt(flatten)]
+    pages: Pages,
+}
+
+/// List the items of a playlist
+#[derive(StructOpt, Clone)]
+struct PlaylistShow {
+    /// Name, id or uri of the playlist
+    playlist: String,
+
+    #[structopt(flatten)]
+    pages: Pages,
+}
+
+/// Create a new playlist
+#[derive(StructOpt, Clone)]
+struct PlaylistCreate {
+    /// Name of the new playlist
+    name: String,
+
+    /// Description of the new playlist
+    #[structopt(long)]
+    description: Option<String>,
+
+    /// Make the playlist public
+    #[structopt(long)]
+    public: bool,
+}
+
+/// Add tracks or episodes to a playlist
+#[derive(StructOpt, Clone)]
+struct PlaylistAdd {
+    /// Name, id or uri of the playlist
+    playlist: String,
+
+    /// Spotify uris or urls, numbers of results from the last search, or search queries. Adds
+    /// the currently playing item if omitted
+    items: Vec<String>,
+
+    /// Position to insert the items at, starting at 1. Appends the items if omitted
+    #[structopt(long, short = "p")]
+    position: Option<u32>,
+}
+
+/// Remove all occurrences of tracks or episodes from a playlist
+#[derive(StructOpt, Clone)]
+struct PlaylistRemove {
+    /// Name, id or uri of the playlist
+    playlist: String,
+
+    /// Spotify uris or urls, numbers of results from the last search, or search queries. Removes
+    /// the currently playing item if omitted
+    items: Vec<String>,
+}
+
+/// Move items within a playlist
+#[derive(StructOpt, Clone)]
+struct PlaylistReorder {
+    /// Name, id or uri of the playlist
+    playlist: String,
+
+    /// Position of the first item to move, starting at 1
+    from: u32,
+
+    /// Position of the item to move the items in front of, starting at 1. One past the last
+    /// position moves the items to the end
+    before: u32,
+
+    /// Number of items to move
+    #[structopt(long, short = "n", default_value = "1")]
+    length: u32,
+}
+
+/// Rename a playlist
+#[derive(StructOpt, Clone)]
+struct PlaylistRename {
+    /// Name, id or uri of the playlist
+    playlist: String,
+
+    /// New name of the playlist
+    name: String,
+}
+
+/// Follow a playlist
+#[derive(StructOpt, Clone)]
+struct PlaylistFollow {
+    /// Uri, url or id of the playlist, or the number of a result from the last search
+    playlist: String,
+}
+
+/// Unfollow a playlist, which for playlists owned by the user is how they are deleted
+#[derive(StructOpt, Clone)]
+struct PlaylistUnfollow {
+    /// Name, id or uri of the playlist
+    playlist: String,
+}
+
+/// Lists recently played tracks, most recent first
+#[derive(StructOpt, Clone)]
+struct History {
+    /// Maximum number of tracks, at most 50
+    #[structopt(long, short = "l", default_value = "20")]
+    limit: u32,
+
+    /// Only list tracks played after this date or time, e.g. 2020-01-31 or
+    /// 2020-01-31T12:00:00Z
+    #[structopt(long, parse(try_from_str = crate::args::parse_datetime))]
+    since: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Lists the user's most played items
+#[derive(StructOpt, Clone)]
+enum Top {
+    #[structopt(alias = "t")]
+    Tracks(TopList),
+
+    #[structopt(alias = "a")]
+    Artists(TopList),
+}
+
+/// List top items, ranked from most to least played
+#[derive(StructOpt, Clone)]
+struct TopList {
+    /// Period to compute top items over, short is about 4 weeks, medium about 6 months and long
+    /// several years
+    #[structopt(long, short = "r", default_value = "medium")]
+    range: crate::args::TimeRange,
+
+    #[structopt(flatten)]
+    pages: Pages,
 }
 
 /// Eject a client's token
-#[derive(StructOpt)]
+#[derive(StructOpt, Clone)]
 struct ClientEject {
     /// Target clients to remove
     ids: Vec<String>,
 }
 
 /// Set default client
-#[derive(StructOpt)]
+#[derive(StructOpt, Clone)]
 struct ClientDefault {
     /// Id of new default client
     id: String,
 }
 
+/// Set the redirect uris whitelisted for a client, they are tried in order when authorizing
+#[derive(StructOpt, Clone)]
+struct ClientRedirect {
+    /// Target client
+    id: String,
+
+    /// Redirect uris, e.g. http://localhost:9524, the default when none are given
+    uris: Vec<String>,
+}
+
 /// Remove a client
-#[derive(StructOpt)]
+#[derive(StructOpt, Clone)]
 struct ClientRemove {
     /// Target clients
     ids: Vec<String>,
 }
 
 /// Add new client
-#[derive(StructOpt)]
+#[derive(StructOpt, Clone)]
 struct ClientNew {}
 
 /// List all existing clients
-#[derive(StructOpt)]
+#[derive(StructOpt, Clone)]
 struct ClientList {}
 
 /// Gets metadata about the currently playing song
-#[derive(StructOpt)]
+#[derive(StructOpt, Clone)]
 struct Status {}
 
+/// Searches for tracks, albums, artists, playlists, shows and episodes
+///
+/// Results are numbered and remembered until the next search, the numbers can be given to
+/// `play` in place of a uri.
+#[derive(StructOpt, Clone)]
+struct Search {
+    /// Search query, may contain field filters such as artist:, album:, year: and genre:
+    #[structopt(required = true)]
+    query: Vec<String>,
+
+    /// Types of items to search for, one or more of track, album, artist, playlist, show and
+    /// episode, repeated as in `-t track -t album`. Defaults to all types
+    #[structopt(long = "type", short = "t", number_of_values = 1)]
+    types: Vec<ItemType>,
+
+    /// Only match items by this artist
+    #[structopt(long)]
+    artist: Option<String>,
+
+    /// Only match items from this album
+    #[structopt(long)]
+    album: Option<String>,
+
+    /// Only match items released in this year or range of years, e.g. 1990-1999
+    #[structopt(long)]
+    year: Option<String>,
+
+    /// Only match items of this genre
+    #[structopt(long)]
+    genre: Option<String>,
+
+    /// Maximum number of results per type, at most 50
+    #[structopt(long, short = "l", default_value = "10")]
+    limit: u32,
+
+    /// Index of the first result per type
+    #[structopt(long, default_value = "0")]
+    offset: u32,
+
+    /// Only return content available in this market, an ISO 3166-1 alpha-2 country code
+    #[structopt(long, short = "m")]
+    market: Option<String>,
+}
+
 /// Starts or resumes playback
-#[derive(StructOpt)]
-struct Play {}
+#[derive(StructOpt, Clone)]
+struct Play {
+    /// Spotify uri or url, the number of a result from the last search, or a search query whose
+    /// best match is played. Resumes playback if omitted
+    query: Vec<String>,
+
+    /// Search for tracks, the default. Also forces searching for queries that are numbers
+    #[structopt(long, short = "t", conflicts_with_all = &["album", "playlist", "artist"])]
+    track: bool,
+
+    /// Search for albums
+    #[structopt(long, short = "a", conflicts_with_all = &["playlist", "artist"])]
+    album: bool,
+
+    /// Search for playlists
+    #[structopt(long, short = "p", conflicts_with = "artist")]
+    playlist: bool,
+
+    /// Search for artists
+    #[structopt(long, short = "r")]
+    artist: bool,
+
+    /// Track number within the album or playlist to start at, starting at 1
+    #[structopt(long)]
+    offset: Option<u32>,
+
+    /// Position within the track to start at, as 1:23 or 83s
+    #[structopt(long, parse(try_from_str = crate::args::parse_time))]
+    position: Option<u64>,
+}
 
 /// Pauses playback
-#[derive(StructOpt)]
+#[derive(StructOpt, Clone)]
 struct Pause {}
 
+/// Pauses playback if playing, resumes it otherwise
+#[derive(StructOpt, Clone)]
+struct Toggle {}
+
+/// Skips to the next track
+#[derive(StructOpt, Clone)]
+struct Next {}
+
+/// Skips to the previous track
+#[derive(StructOpt, Clone)]
+struct Prev {}
+
+/// Seeks within the current track
+#[derive(StructOpt, Clone)]
+struct Seek {
+    /// Position as 1:23 or 83s, relative to the current position as +10s or -30s, or a
+    /// percentage of the track as 50%
+    #[structopt(allow_hyphen_values = true)]
+    position: crate::args::SeekTarget,
+}
+
+/// Sets the playback volume
+#[derive(StructOpt, Clone)]
+struct Volume {
+    /// Volume from 0 to 100, or relative to the current volume as +5 or -5
+    #[structopt(allow_hyphen_values = true)]
+    volume: crate::args::VolumeTarget,
+}
+
+/// Sets shuffle mode
+#[derive(StructOpt, Clone)]
+struct Shuffle {
+    /// One of on, off or toggle
+    state: crate::args::Switch,
+}
+
+/// Sets repeat mode
+#[derive(StructOpt, Clone)]
+struct Repeat {
+    /// One of off, track or context
+    mode: crate::args::RepeatMode,
+}
+
 impl CLI {
     pub fn run(self, config: &mut Config) -> Result<()> {
-        let spotify = LazySpotify {
+        let output = match self.output {
+            Some(output) => output,
+            None => config.output().unwrap_or("human").parse()?,
+        };
+
+        let mut spotify = LazySpotify {
             client_id: self.client_id,
+            device: self.device,
+            auth: self.auth,
+            scopes: self.cmd.scopes(),
+            refreshed: Default::default(),
+            max_attempts: self.max_attempts,
             generator: CLI::gen_spotify,
             cell: None,
         };
 
-        self.cmd.run(spotify, config)
+        let result = Self::run_command(self.cmd, &mut spotify, config, &output);
+
+        // A token refreshed before the command failed may hold a rotated refresh token
+        let saved = spotify.save_refreshed(config);
+
+        result.and(saved)
+    }
+
+    /// Runs `cmd`, authorizing again when spotify lacks a scope that the recorded scopes claim
+    /// was granted, or revoked the refresh token. Only commands that can be repeated are run
+    /// again, others may have done part of their work before failing.
+    fn run_command(
+        cmd: Command,
+        spotify: &mut LazySpotify,
+        config: &mut Config,
+        output: &Format,
+    ) -> Result<()> {
+        let err = match cmd.clone().run(spotify, config, output) {
+            Err(err) if is_insufficient_scope(&err) => {
+                log::info!("spotify denied access for lack of scope: {}", err);
+
+                if !crate::dialouge::authorize_scopes(spotify.scopes)? {
+                    return Err(err);
+                }
+
+                err
+            }
+            Err(err) if is_invalid_grant(&err) => {
+                log::warn!(
+                    "refresh token is no longer valid, authorizing again: {}",
+                    err
+                );
+
+                err
+            }
+            result => return result,
+        };
+
+        let scopes = spotify.scopes;
+        spotify.login(config, scopes)?;
+
+        if cmd.is_repeatable() {
+            cmd.run(spotify, config, output)
+        } else {
+            Err(err.context(
+                "Authorized again, the command was not repeated as it may have partly run, \
+                run it again if it did not",
+            ))
+        }
     }
 
-    fn gen_spotify(client_id: Option<String>, config: &mut Config) -> Result<Spotify<Scope>> {
+    fn gen_spotify(spotify: &LazySpotify, config: &mut Config) -> Result<Spotify> {
         let enc_key = crate::keyring::get_or_create_key()?;
 
-        let id = client_id
-            .or_else(|| config.default().cloned())
-            .ok_or(anyhow!("Client id required!"))?;
+        let id = spotify.client_id(config)?.clone();
 
         log::trace!("building spotify client using id = '{}'", &id);
 
         let (secret, token) = config
             .get_client_data(&id, &enc_key)
-            .ok_or(anyhow!("No client with id = '{}'", id))??;
+            .ok_or_else(|| ApplicationError::NoMatch("client", id.clone()))??;
+
+        let credentials = Credentials::new(id, secret, &spotify.auth, config)?;
 
-        let client = spotify_web::Client::new(&id, &secret, Scope::create());
+        let token = match token {
+            None => {
+                log::info!("no token, fetching...");
 
-        let auth = client
-            .authorization()
-            .redirect_uri("http://localhost:9524")
-            .build();
+                let scopes = crate::scopes::union(&[], spotify.scopes);
+                authorize(&credentials, &spotify.auth, scopes, config, &enc_key)?
+            }
+            Some(token) => {
+                let missing = crate::scopes::missing(&token.scopes, spotify.scopes);
 
-        if let Some(token) = token {
-            if token.has_expired() {
-                log::debug!("token expired, refreshing");
+                if !missing.is_empty() {
+                    log::info!("token lacks scopes {:?}", missing);
 
-                let token = Token::new(auth.refresh_token(token.token)?);
-                config.set_token(&id, &token, &enc_key)?;
+                    if !crate::dialouge::authorize_scopes(&missing)? {
+                        return Err(ApplicationError::MissingScopes(missing.join(", ")).into());
+                    }
 
-                Ok(client.with_access_token(&token.token)?)
-            } else {
-                log::debug!("previous token has not expired yet, reusing it");
+                    let scopes = crate::scopes::union(&token.scopes, spotify.scopes);
+                    authorize(&credentials, &spotify.auth, scopes, config, &enc_key)?
+                } else if token.has_expired() {
+                    log::debug!("token expired, refreshing");
 
-                Ok(client.with_access_token(&token.token)?)
-            }
-        } else {
-            log::info!("no token, fetching...");
-            let code = crate::oauth::code(auth.url().as_str())?;
-            let token = Token::new(auth.fetch_token2(code.as_str(), None)?);
+                    match refresh(&credentials, &token) {
+                        Ok(token) => {
+                            config.set_token(&credentials.id, &token, &enc_key)?;
+                            token
+                        }
+                        Err(err) if is_invalid_grant(&err) => {
+                            log::warn!(
+                                "refresh token is no longer valid, authorizing again: {}",
+                                err
+                            );
+                            authorize(&credentials, &spotify.auth, token.scopes, config, &enc_key)?
+                        }
+                        Err(err) => return Err(err),
+                    }
+                } else {
+                    log::debug!("previous token has not expired yet, reusing it");
 
-            config.set_token(&id, &token, &enc_key)?;
+                    token
+                }
+            }
+        };
 
-            Ok(client.with_access_token(&token.token)?)
-        }
+        Ok(session(credentials, token, spotify))
     }
-}
 
-impl Command {
-    fn run(self, spotify: LazySpotify, config: &mut Config) -> Result<()> {
-        match self {
-            Self::Status(x) => x.run(spotify, config),
-            Self::Play(x) => x.run(spotify, config),
-            Self::Pause(x) => x.run(spotify, config),
-            Self::Client { cmd } => cmd.run(config),
-        }
+    fn login(
+        id: &str,
+        spotify: &LazySpotify,
+        scopes: &[&str],
+        config: &mut Config,
+    ) -> Result<Spotify> {
+        let enc_key = crate::keyring::get_or_create_key()?;
+
+        let secret = config
+            .get_secret(id, &enc_key)
+            .ok_or_else(|| ApplicationError::NoMatch("client", id.to_owned()))??;
+
+        // Logging in replaces a token that can not be decrypted, so it only loses its scopes
+        let token = match config.get_token(id, &enc_key) {
+            Some(Ok(token)) => token,
+            Some(Err(err)) => {
+                log::warn!("ignoring the token of client {}: {}", id, err);
+                None
+            }
+            None => None,
+        };
+
+        let granted = token.map(|token| token.scopes).unwrap_or_default();
+        let scopes = crate::scopes::union(&granted, scopes);
+
+        let credentials = Credentials::new(id.to_owned(), secret, &spotify.auth, config)?;
+        let token = authorize(&credentials, &spotify.auth, scopes, config, &enc_key)?;
+
+        Ok(session(credentials, token, spotify))
     }
 }
 
-impl Client {
-    fn run(self, config: &mut Config) -> Result<()> {
-        match self {
-            Self::New(x) => x.run(config),
-            Self::List(x) => x.run(config),
-            Self::Remove(x) => x.run(config),
-            Self::Eject(x) => x.run(config),
-            Self::Default(x) => x.run(config),
-        }
-    }
+/// What identifies a client to spotify when authorizing and refreshing tokens.
+struct Credentials {
+    id: String,
+
+    /// `None` for clients that authorize with PKCE
+    secret: Option<String>,
+
+    redirect_uri: String,
 }
 
-impl ClientDefault {
-    fn run(self, config: &mut Config) -> Result<()> {
-        config.set_default(self.id).map_err(|id| {
-            anyhow::anyhow!("Could not set default client to non-existing id = '{}'", id)
-        })?;
+impl Credentials {
+    fn new(
+        id: String,
+        secret: Option<String>,
+        options: &AuthOptions,
+        config: &Config,
+    ) -> Result<Self> {
+        let redirect_uri = options.redirect_uris(&id, config)?.remove(0);
 
-        Ok(())
+        Ok(Self {
+            id,
+            secret,
+            redirect_uri,
+        })
     }
 }
 
-impl ClientEject {
+/// Client using `token` that refreshes it when spotify rejects it, the refreshed token ends up
+/// in `spotify.refreshed`. The latest token is only replaced once refreshing it succeeded, so a
+/// rotated refresh token is not lost when a later refresh fails.
+fn session(credentials: Credentials, token: Token, spotify: &LazySpotify) -> Spotify {
+    let access_token = token.token.access_token.clone();
+    let refreshed = spotify.refreshed.clone();
+
+    let client = Spotify::new(access_token).with_max_attempts(spotify.max_attempts);
+
+    client.with_refresh(Box::new(move || {
+        let mut refreshed = refreshed.borrow_mut();
+
+        let next = refresh(&credentials, refreshed.as_ref().unwrap_or(&token))?;
+        let access_token = next.token.access_token.clone();
+
+        *refreshed = Some(next);
+
+        Ok(access_token)
+    }))
+}
+
+/// Refreshes `token`, keeping its refresh token unless spotify rotated it.
+fn refresh(credentials: &Credentials, token: &Token) -> Result<Token> {
+    let inner = match &credentials.secret {
+        Some(secret) => {
+            // spotify_web takes the token by value, hand it a copy so `token` survives failures
+            let copy = serde_json::from_value(serde_json::to_value(&token.token)?)?;
+
+            let mut inner = spotify_web::Client::new(&credentials.id, secret, Scope::create())
+                .authorization()
+                .redirect_uri(&credentials.redirect_uri)
+                .build()
+                .refresh_token(copy)?;
+
+            if inner.refresh_token.is_none() {
+                inner.refresh_token = token.token.refresh_token.clone();
+            }
+
+            inner
+        }
+        None => crate::oauth::refresh_token(&credentials.id, &token.token)?,
+    };
+
+    Ok(Token::new(inner, token.scopes.clone()))
+}
+
+/// Runs the authorization flow for a client and stores the resulting token.
+fn authorize(
+    credentials: &Credentials,
+    options: &AuthOptions,
+    scopes: Vec<String>,
+    config: &mut Config,
+    enc_key: &LessSafeKey,
+) -> Result<Token> {
+    let id = credentials.id.as_str();
+    let secret = credentials.secret.as_deref();
+
+    let redirect_uris = options.redirect_uris(id, config)?;
+
+    let redirect = if options.no_browser {
+        crate::oauth::Redirect::paste(&redirect_uris[0])?
+    } else {
+        crate::oauth::Redirect::listen(&redirect_uris)?
+    };
+
+    let redirect_uri = redirect.redirect_uri().to_owned();
+
+    let client = spotify_web::Client::new(id, secret.unwrap_or_default(), Scope::create());
+    let auth = client.authorization().redirect_uri(&redirect_uri).build();
+
+    let mut url = auth.url().clone();
+    crate::oauth::set_scopes(&mut url, &scopes);
+
+    let pkce = match secret {
+        Some(_) => None,
+        None => {
+            log::debug!("client has no secret, authorizing with pkce");
+
+            let pkce = crate::oauth::Pkce::new()?;
+
+            url.query_pairs_mut()
+                .append_pair("code_challenge_method", "S256")
+                .append_pair("code_challenge", &pkce.challenge);
+
+            Some(pkce)
+        }
+    };
+
+    let code = redirect.code(url, std::time::Duration::from_secs(options.auth_timeout))?;
+
+    let token = Token::new(
+        match pkce {
+            Some(pkce) => crate::oauth::fetch_token(id, &code, &redirect_uri, &pkce.verifier)?,
+            None => auth.fetch_token2(code.as_str(), None)?,
+        },
+        scopes,
+    );
+
+    config.set_token(id, &token, enc_key)?;
+
+    Ok(token)
+}
+
+/// Resolves `query` as a uri or url, as the number of a result of the last search, or by
+/// searching for the best match of type `kind`. Giving `kind` always searches.
+fn resolve_uri(spotify: &Spotify, query: &str, kind: Option<ItemType>) -> Result<SpotifyUri> {
+    if let Ok(uri) = query.parse() {
+        return Ok(uri);
+    }
+
+    if let (None, Ok(index)) = (kind, query.parse()) {
+        return crate::cache::lookup(index)?.uri.parse();
+    }
+
+    let kind = kind.unwrap_or(ItemType::Track);
+
+    spotify
+        .search(query, &[kind], 1, 0, None)?
+        .into_items()
+        .into_iter()
+        .next()
+        .ok_or_else(|| ApplicationError::NoMatch(kind.as_str(), query.to_owned()))?
+        .uri
+        .parse()
+}
+
+/// The uri given as `item`, or that of the currently playing item.
+fn item_or_current(spotify: &Spotify, item: Option<&str>) -> Result<SpotifyUri> {
+    match item {
+        Some(item) => resolve_uri(spotify, item, None),
+        None => spotify
+            .playback_state()?
+            .and_then(|state| state.item)
+            .map(crate::model::ItemSummary::from)
+            .ok_or(ApplicationError::NothingPlaying)?
+            .uri
+            .parse(),
+    }
+}
+
+/// Id of the playlist given by uri or url, or by name or id among the user's playlists.
+fn playlist_id(spotify: &Spotify, query: &str) -> Result<String> {
+    if let Ok(uri) = query.parse::<SpotifyUri>() {
+        anyhow::ensure!(uri.kind == ItemType::Playlist, "{} is not a playlist", uri);
+        return Ok(uri.id);
+    }
+
+    let playlists = spotify.all_playlists()?;
+    Ok(crate::api::resolve_playlist(&playlists, query)?.id.clone())
+}
+
+/// Uris of tracks or episodes given as `items`, or that of the currently playing item.
+fn playable_uris(spotify: &Spotify, items: &[String]) -> Result<Vec<String>> {
+    let uris = if items.is_empty() {
+        vec![item_or_current(spotify, None)?]
+    } else {
+        items
+            .iter()
+            .map(|item| resolve_uri(spotify, item, None))
+            .collect::<Result<_>>()?
+    };
+
+    uris.into_iter()
+        .map(|uri| {
+            anyhow::ensure!(
+                !uri.kind.is_context(),
+                "Only tracks and episodes can be added to playlists, got {}",
+                uri
+            );
+
+            Ok(uri.to_string())
+        })
+        .collect()
+}
+
+impl Pages {
+    /// Fetches the selected pages using `page(limit, offset)`, numbering items from 1.
+    fn fetch<T>(
+        &self,
+        mut page: impl FnMut(u32, u32) -> Result<crate::model::Paging<T>>,
+    ) -> Result<Vec<(u32, T)>> {
+        let limit = if self.all { 50 } else { self.limit };
+
+        anyhow::ensure!(
+            (1..=50).contains(&limit),
+            "--limit must be between 1 and 50"
+        );
+
+        let mut items = Vec::new();
+        let mut offset = self.offset;
+
+        loop {
+            let page = page(limit, offset)?;
+            let has_next = page.next.is_some();
+
+            items.extend((offset + 1..).zip(page.items));
+
+            if !self.all || !has_next {
+                return Ok(items);
+            }
+
+            offset += limit;
+        }
+    }
+}
+
+/// Whether spotify refused a request because the token lacks a scope.
+fn is_insufficient_scope(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<ApplicationError>(),
+        Some(ApplicationError::SpotifyApi { status: 403, message })
+            if message.to_lowercase().contains("scope")
+    )
+}
+
+/// Whether a refresh token was rejected, which happens when it was revoked or rotated
+/// elsewhere, so the client has to be authorized again.
+fn is_invalid_grant(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<ApplicationError>() {
+        Some(ApplicationError::Authorization { error, .. }) => error == "invalid_grant",
+        // spotify_web errors only carry the response
+        _ => err.to_string().contains("invalid_grant"),
+    }
+}
+
+impl Command {
+    /// Scopes the command needs the client to be authorized for.
+    fn scopes(&self) -> &'static [&'static str] {
+        use crate::scopes::*;
+
+        match self {
+            Self::Client { .. } | Self::Login(_) | Self::Logout(_) | Self::Search(_) => &[],
+            Self::Whoami(_) => &[USER_READ_PRIVATE],
+            Self::Status(_)
+            | Self::Queue {
+                cmd: Queue::List(_),
+            } => PLAYBACK_READ,
+            Self::Device { .. }
+            | Self::Queue { cmd: Queue::Add(_) }
+            | Self::Play(_)
+            | Self::Pause(_)
+            | Self::Toggle(_)
+            | Self::Next(_)
+            | Self::Prev(_)
+            | Self::Seek(_)
+            | Self::Volume(_)
+            | Self::Shuffle(_)
+            | Self::Repeat(_) => PLAYBACK,
+            Self::Like(_) | Self::Unlike(_) => LIBRARY_MODIFY,
+            Self::Library { .. } => &[USER_LIBRARY_READ],
+            Self::Playlist {
+                cmd: Playlist::List(_) | Playlist::Show(_),
+            } => PLAYLIST_READ,
+            Self::Playlist { .. } => PLAYLIST_MODIFY,
+            Self::History(_) => &[USER_READ_RECENTLY_PLAYED],
+            Self::Top { .. } => &[USER_TOP_READ],
+        }
+    }
+
+    /// Whether running the command twice has the same effect as running it once.
+    fn is_repeatable(&self) -> bool {
+        match self {
+            Self::Status(_)
+            | Self::Search(_)
+            | Self::Whoami(_)
+            | Self::Device { .. }
+            | Self::Pause(_)
+            | Self::Like(_)
+            | Self::Unlike(_)
+            | Self::Library { .. }
+            | Self::History(_)
+            | Self::Top { .. } => true,
+            Self::Queue { cmd } => matches!(cmd, Queue::List(_)),
+            Self::Playlist { cmd } => matches!(cmd, Playlist::List(_) | Playlist::Show(_)),
+            _ => false,
+        }
+    }
+
+    fn run(self, spotify: &mut LazySpotify, config: &mut Config, output: &Format) -> Result<()> {
+        match self {
+            Self::Status(x) => x.run(spotify, config, output),
+            Self::Search(x) => x.run(spotify, config, output),
+            Self::Play(x) => x.run(spotify, config),
+            Self::Pause(x) => x.run(spotify, config),
+            Self::Toggle(x) => x.run(spotify, config),
+            Self::Next(x) => x.run(spotify, config),
+            Self::Prev(x) => x.run(spotify, config),
+            Self::Seek(x) => x.run(spotify, config),
+            Self::Volume(x) => x.run(spotify, config),
+            Self::Shuffle(x) => x.run(spotify, config),
+            Self::Repeat(x) => x.run(spotify, config),
+            Self::Client { cmd } => cmd.run(config, output),
+            Self::Login(x) => x.run(spotify, config),
+            Self::Logout(x) => x.run(spotify, config),
+            Self::Whoami(x) => x.run(spotify, config, output),
+            Self::Device { cmd } => cmd.run(spotify, config, output),
+            Self::Queue { cmd } => cmd.run(spotify, config, output),
+            Self::Like(x) => x.run(spotify, config),
+            Self::Unlike(x) => x.run(spotify, config),
+            Self::Library { cmd } => cmd.run(spotify, config, output),
+            Self::Playlist { cmd } => cmd.run(spotify, config, output),
+            Self::History(x) => x.run(spotify, config, output),
+            Self::Top { cmd } => cmd.run(spotify, config, output),
+        }
+    }
+}
+
+impl Top {
+    fn run(self, spotify: &mut LazySpotify, config: &mut Config, output: &Format) -> Result<()> {
+        let spotify = spotify.as_mut(config)?;
+
+        match self {
+            Self::Tracks(x) => x.run::<crate::model::Track>(spotify, "tracks", output),
+            Self::Artists(x) => x.run::<crate::model::SimpleArtist>(spotify, "artists", output),
+        }
+    }
+}
+
+impl Playlist {
+    fn run(self, spotify: &mut LazySpotify, config: &mut Config, output: &Format) -> Result<()> {
+        let spotify = spotify.as_mut(config)?;
+
+        match self {
+            Self::List(x) => x.run(spotify, output),
+            Self::Show(x) => x.run(spotify, output),
+            Self::Create(x) => x.run(spotify, output),
+            Self::Add(x) => x.run(spotify),
+            Self::Remove(x) => x.run(spotify),
+            Self::Reorder(x) => x.run(spotify),
+            Self::Rename(x) => x.run(spotify),
+            Self::Follow(x) => x.run(spotify),
+            Self::Unfollow(x) => x.run(spotify),
+        }
+    }
+}
+
+impl Library {
+    fn run(self, spotify: &mut LazySpotify, config: &mut Config, output: &Format) -> Result<()> {
+        let spotify = spotify.as_mut(config)?;
+
+        match self {
+            Self::Tracks(x) => x.run::<crate::model::Track>(spotify, ItemType::Track, output),
+            Self::Albums(x) => x.run::<crate::model::SimpleAlbum>(spotify, ItemType::Album, output),
+            Self::Shows(x) => x.run::<crate::model::SimpleShow>(spotify, ItemType::Show, output),
+            Self::Contains(x) => x.run(spotify, output),
+        }
+    }
+}
+
+impl Queue {
+    fn run(self, spotify: &mut LazySpotify, config: &mut Config, output: &Format) -> Result<()> {
+        match self {
+            Self::Add(x) => x.run(spotify, config),
+            Self::List(x) => x.run(spotify, config, output),
+        }
+    }
+}
+
+impl Device {
+    fn run(self, spotify: &mut LazySpotify, config: &mut Config, output: &Format) -> Result<()> {
+        match self {
+            Self::List(x) => x.run(spotify, config, output),
+            Self::Transfer(x) => x.run(spotify, config),
+            Self::Default(x) => x.run(spotify, config),
+        }
+    }
+}
+
+impl Client {
+    fn run(self, config: &mut Config, output: &Format) -> Result<()> {
+        match self {
+            Self::New(x) => x.run(config),
+            Self::List(x) => x.run(config, output),
+            Self::Remove(x) => x.run(config),
+            Self::Eject(x) => x.run(config),
+            Self::Default(x) => x.run(config),
+            Self::Redirect(x) => x.run(config),
+        }
+    }
+}
+
+impl DeviceList {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config, output: &Format) -> Result<()> {
+        let default = spotify
+            .client_id(config)
+            .ok()
+            .and_then(|id| config.default_device(id))
+            .cloned();
+
+        let devices: Vec<_> = spotify
+            .as_mut(config)?
+            .devices()?
+            .into_iter()
+            .map(|device| output::DeviceRecord::new(device, default.as_ref()))
+            .collect();
+
+        output::list(output, &devices)
+    }
+}
+
+impl DeviceTransfer {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let spotify = spotify.as_mut(config)?;
+        let devices = spotify.devices()?;
+        let device = crate::api::resolve_device(&devices, &self.device)?;
+
+        let id = device
+            .id
+            .as_ref()
+            .ok_or(anyhow!("Device '{}' can not be controlled", device.name))?;
+
+        spotify.transfer_playback(id, self.play)
+    }
+}
+
+impl DeviceDefault {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let device = match &self.device {
+            Some(query) => {
+                let devices = spotify.as_mut(config)?.devices()?;
+                let device = crate::api::resolve_device(&devices, query)?;
+
+                Some(
+                    device
+                        .id
+                        .clone()
+                        .ok_or(anyhow!("Device '{}' can not be controlled", device.name))?,
+                )
+            }
+            None => None,
+        };
+
+        let id = spotify.client_id(config)?.clone();
+        config.set_default_device(&id, device);
+
+        Ok(())
+    }
+}
+
+impl ClientDefault {
+    fn run(self, config: &mut Config) -> Result<()> {
+        config.set_default(self.id).map_err(|id| {
+            anyhow::anyhow!("Could not set default client to non-existing id = '{}'", id)
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Login {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let mut scopes = crate::scopes::ALL.to_vec();
+        scopes.extend(self.scopes.iter().map(String::as_str));
+
+        spotify.login(config, &scopes)?;
+
+        Ok(())
+    }
+}
+
+impl Logout {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let id = spotify.client_id(config)?.clone();
+
+        anyhow::ensure!(config.has_client(&id), "No client with id = '{}'", id);
+
+        config.eject_token(&id);
+
+        Ok(())
+    }
+}
+
+impl Whoami {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config, output: &Format) -> Result<()> {
+        let user = spotify.as_mut(config)?.current_user()?;
+        let client = spotify.client_id(config)?;
+
+        output::one(output, &output::UserRecord::new(client, user))
+    }
+}
+
+impl ClientRedirect {
+    fn run(self, config: &mut Config) -> Result<()> {
+        anyhow::ensure!(
+            config.has_client(&self.id),
+            "No client with id = '{}'",
+            self.id
+        );
+
+        for uri in &self.uris {
+            url::Url::parse(uri)
+                .map_err(|err| anyhow!("Invalid redirect uri '{}': {}", uri, err))?;
+        }
+
+        config.set_redirect_uris(&self.id, self.uris);
+
+        Ok(())
+    }
+}
+
+impl ClientEject {
     fn run(&self, config: &mut Config) -> Result<()> {
         for id in &self.ids {
             config.eject_token(id);
@@ -226,24 +1438,19 @@ impl ClientEject {
 }
 
 impl ClientList {
-    fn run(&self, config: &mut Config) -> Result<()> {
+    fn run(&self, config: &mut Config, output: &Format) -> Result<()> {
         let default = config.default();
 
-        for (client, token_is_some) in config.clients() {
-            writeln!(
-                std::io::stdout(),
-                "{:<33}{}{}",
-                client,
-                if token_is_some { "[token]" } else { "       " },
-                if default == Some(client) {
-                    "[default]"
-                } else {
-                    ""
-                },
-            )?;
-        }
+        let clients: Vec<_> = config
+            .clients()
+            .map(|(client, token_is_some)| output::ClientRecord {
+                id: client,
+                token: token_is_some,
+                default: default == Some(client),
+            })
+            .collect();
 
-        Ok(())
+        output::list(output, &clients)
     }
 }
 
@@ -261,35 +1468,494 @@ impl ClientNew {
     fn run(&self, config: &mut Config) -> Result<()> {
         let enc_key = crate::keyring::get_or_create_key()?;
 
-        let (id, secret) = crate::dialouge::new_client()?;
+        let (id, secret, redirect_uris) = crate::dialouge::new_client()?;
 
         if crate::dialouge::set_default()? {
             config.set_default_force(&id);
         }
 
-        config.add_client(id.clone(), secret, &enc_key)?;
+        config.add_client(id.clone(), secret, redirect_uris, &enc_key)?;
 
         Ok(())
     }
 }
 
 impl Status {
-    fn run(&self, mut spotify: LazySpotify, config: &mut Config) -> Result<()> {
-        let output = spotify.as_mut(config)?.currently_playing(None)?.text()?;
-        crate::dialouge::display(&output)
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config, output: &Format) -> Result<()> {
+        let state = spotify.as_mut(config)?.playback_state()?;
+        output::one(output, &output::StatusRecord::new(state))
     }
 }
 
 impl Play {
-    fn run(&self, mut spotify: LazySpotify, config: &mut Config) -> Result<()> {
-        let output = spotify.as_mut(config)?.resume_playback(None)?.text()?;
-        crate::dialouge::display(&output)
+    fn item_type(&self) -> Option<ItemType> {
+        if self.track {
+            Some(ItemType::Track)
+        } else if self.album {
+            Some(ItemType::Album)
+        } else if self.playlist {
+            Some(ItemType::Playlist)
+        } else if self.artist {
+            Some(ItemType::Artist)
+        } else {
+            None
+        }
+    }
+
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let device = spotify.device_id(config)?;
+        let spotify = spotify.as_mut(config)?;
+
+        if self.query.is_empty() {
+            anyhow::ensure!(
+                self.offset.is_none() && self.position.is_none(),
+                "--offset and --position require something to play"
+            );
+
+            return spotify.resume_playback(device.as_deref());
+        }
+
+        let uri = resolve_uri(spotify, &self.query.join(" "), self.item_type())?;
+
+        let offset = match self.offset {
+            Some(0) => anyhow::bail!("--offset starts at 1"),
+            Some(offset) => {
+                anyhow::ensure!(
+                    matches!(
+                        uri.kind,
+                        ItemType::Album | ItemType::Playlist | ItemType::Show
+                    ),
+                    "--offset requires an album, playlist or show"
+                );
+
+                Some(offset - 1)
+            }
+            None => None,
+        };
+
+        log::debug!("playing {}", uri);
+
+        spotify.start_playback(&uri, offset, self.position, device.as_deref())
     }
 }
 
 impl Pause {
-    fn run(&self, mut spotify: LazySpotify, config: &mut Config) -> Result<()> {
-        let output = spotify.as_mut(config)?.pause_playback(None)?.text()?;
-        crate::dialouge::display(&output)
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let device = spotify.device_id(config)?;
+        spotify.as_mut(config)?.pause_playback(device.as_deref())
+    }
+}
+
+impl Toggle {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let device = spotify.device_id(config)?;
+        let spotify = spotify.as_mut(config)?;
+
+        let playing = spotify
+            .playback_state()?
+            .is_some_and(|state| state.is_playing);
+
+        if playing {
+            spotify.pause_playback(device.as_deref())
+        } else {
+            spotify.resume_playback(device.as_deref())
+        }
+    }
+}
+
+impl Next {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let device = spotify.device_id(config)?;
+        spotify.as_mut(config)?.next(device.as_deref())
+    }
+}
+
+impl Prev {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let device = spotify.device_id(config)?;
+        spotify.as_mut(config)?.previous(device.as_deref())
+    }
+}
+
+impl Seek {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let device = spotify.device_id(config)?;
+        let spotify = spotify.as_mut(config)?;
+
+        let position = match self.position {
+            crate::args::SeekTarget::Absolute(ms) => ms,
+            target => {
+                let state = spotify
+                    .playback_state()?
+                    .ok_or(ApplicationError::NothingPlaying)?;
+
+                let duration = state
+                    .item
+                    .as_ref()
+                    .map_or(0, crate::model::PlayingItem::duration_ms);
+
+                target.position_ms(state.progress_ms.unwrap_or(0), duration)
+            }
+        };
+
+        spotify.seek(position, device.as_deref())
+    }
+}
+
+impl Volume {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let device = spotify.device_id(config)?;
+        let spotify = spotify.as_mut(config)?;
+
+        let volume = match self.volume {
+            crate::args::VolumeTarget::Absolute(volume) => volume,
+            target => {
+                let current = spotify
+                    .devices()?
+                    .into_iter()
+                    .find(|d| match &device {
+                        Some(id) => d.id.as_ref() == Some(id),
+                        None => d.is_active,
+                    })
+                    .ok_or_else(|| match &device {
+                        Some(id) => ApplicationError::NoMatch("device", id.clone()),
+                        None => ApplicationError::NoActiveDevice,
+                    })?
+                    .volume_percent
+                    .ok_or(anyhow!("Device does not support volume control"))?;
+
+                target.percent(current)
+            }
+        };
+
+        spotify.volume(volume, device.as_deref())
+    }
+}
+
+impl Shuffle {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let device = spotify.device_id(config)?;
+        let spotify = spotify.as_mut(config)?;
+
+        let current = match self.state {
+            crate::args::Switch::Toggle => spotify
+                .playback_state()?
+                .is_some_and(|state| state.shuffle_state),
+            _ => false,
+        };
+
+        spotify.shuffle(self.state.apply(current), device.as_deref())
+    }
+}
+
+impl Repeat {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let device = spotify.device_id(config)?;
+        spotify
+            .as_mut(config)?
+            .repeat(self.mode.as_str(), device.as_deref())
+    }
+}
+
+impl Search {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config, output: &Format) -> Result<()> {
+        anyhow::ensure!(
+            (1..=50).contains(&self.limit),
+            "--limit must be between 1 and 50"
+        );
+
+        let mut query = self.query.join(" ");
+
+        let filters = [
+            ("artist", &self.artist),
+            ("album", &self.album),
+            ("year", &self.year),
+            ("genre", &self.genre),
+        ];
+
+        for (field, value) in filters.iter() {
+            if let Some(value) = value {
+                query.push_str(&format!(" {}:\"{}\"", field, value));
+            }
+        }
+
+        let types = if self.types.is_empty() {
+            vec![
+                ItemType::Track,
+                ItemType::Album,
+                ItemType::Artist,
+                ItemType::Playlist,
+                ItemType::Show,
+                ItemType::Episode,
+            ]
+        } else {
+            self.types.clone()
+        };
+
+        let results = spotify.as_mut(config)?.search(
+            &query,
+            &types,
+            self.limit,
+            self.offset,
+            self.market.as_deref(),
+        )?;
+
+        let records: Vec<_> = results
+            .into_items()
+            .into_iter()
+            .zip(1..)
+            .map(|(item, index)| output::SearchRecord { index, item })
+            .collect();
+
+        if let Err(e) = crate::cache::store(&records) {
+            log::warn!("could not cache search results: {}", e);
+        }
+
+        output::list(output, &records)
+    }
+}
+
+impl QueueAdd {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let device = spotify.device_id(config)?;
+        let spotify = spotify.as_mut(config)?;
+
+        let kind = if self.episode {
+            Some(ItemType::Episode)
+        } else {
+            None
+        };
+
+        let uri = resolve_uri(spotify, &self.query.join(" "), kind)?;
+
+        anyhow::ensure!(
+            !uri.kind.is_context(),
+            "Only tracks and episodes can be queued, got {}",
+            uri
+        );
+
+        spotify.add_to_queue(&uri, device.as_deref())
+    }
+}
+
+impl QueueList {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config, output: &Format) -> Result<()> {
+        let queue = spotify.as_mut(config)?.queue()?;
+
+        let records: Vec<_> = queue
+            .currently_playing
+            .into_iter()
+            .map(|item| (0, item))
+            .chain((1..).zip(queue.queue))
+            .map(|(position, item)| output::QueueRecord {
+                position,
+                item: item.into(),
+            })
+            .collect();
+
+        output::list(output, &records)
+    }
+}
+
+impl Like {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let spotify = spotify.as_mut(config)?;
+        let uri = item_or_current(spotify, self.item.as_deref())?;
+
+        spotify.save(&uri)
+    }
+}
+
+impl Unlike {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config) -> Result<()> {
+        let spotify = spotify.as_mut(config)?;
+        let uri = item_or_current(spotify, self.item.as_deref())?;
+
+        spotify.unsave(&uri)
+    }
+}
+
+impl LibraryList {
+    fn run<T>(&self, spotify: &Spotify, kind: ItemType, output: &Format) -> Result<()>
+    where
+        T: serde::de::DeserializeOwned + Into<crate::model::ItemSummary>,
+    {
+        let path = crate::api::library_path(kind)?;
+
+        let records: Vec<_> = self
+            .pages
+            .fetch(|limit, offset| spotify.saved::<T>(path, limit, offset))?
+            .into_iter()
+            .map(|(index, saved)| output::AddedRecord {
+                index,
+                added_at: Some(saved.added_at),
+                item: saved.item.into(),
+            })
+            .collect();
+
+        output::list(output, &records)
+    }
+}
+
+impl LibraryContains {
+    fn run(&self, spotify: &Spotify, output: &Format) -> Result<()> {
+        let uri = resolve_uri(spotify, &self.item, None)?;
+
+        output::one(
+            output,
+            &output::SavedRecord {
+                saved: spotify.is_saved(&uri)?,
+                uri: uri.to_string(),
+            },
+        )
+    }
+}
+
+impl PlaylistList {
+    fn run(&self, spotify: &Spotify, output: &Format) -> Result<()> {
+        let records: Vec<_> = self
+            .pages
+            .fetch(|limit, offset| spotify.playlists(limit, offset))?
+            .into_iter()
+            .map(|(index, playlist)| output::PlaylistRecord::new(index, playlist))
+            .collect();
+
+        output::list(output, &records)
+    }
+}
+
+impl PlaylistShow {
+    fn run(&self, spotify: &Spotify, output: &Format) -> Result<()> {
+        let id = playlist_id(spotify, &self.playlist)?;
+
+        let records: Vec<_> = self
+            .pages
+            .fetch(|limit, offset| spotify.playlist_items(&id, limit, offset))?
+            .into_iter()
+            .filter_map(|(index, item)| {
+                Some(output::AddedRecord {
+                    index,
+                    added_at: item.added_at,
+                    item: item.track?.into(),
+                })
+            })
+            .collect();
+
+        output::list(output, &records)
+    }
+}
+
+impl PlaylistCreate {
+    fn run(&self, spotify: &Spotify, output: &Format) -> Result<()> {
+        let user = spotify.current_user()?;
+
+        let playlist = spotify.create_playlist(
+            &user.id,
+            &self.name,
+            self.public,
+            self.description.as_deref(),
+        )?;
+
+        output::one(output, &output::PlaylistRecord::new(1, playlist))
+    }
+}
+
+impl PlaylistAdd {
+    fn run(&self, spotify: &Spotify) -> Result<()> {
+        let id = playlist_id(spotify, &self.playlist)?;
+        let uris = playable_uris(spotify, &self.items)?;
+
+        let position = match self.position {
+            Some(0) => anyhow::bail!("--position starts at 1"),
+            position => position.map(|p| p - 1),
+        };
+
+        spotify.add_to_playlist(&id, &uris, position)
+    }
+}
+
+impl PlaylistRemove {
+    fn run(&self, spotify: &Spotify) -> Result<()> {
+        let id = playlist_id(spotify, &self.playlist)?;
+        let uris = playable_uris(spotify, &self.items)?;
+
+        spotify.remove_from_playlist(&id, &uris)
+    }
+}
+
+impl PlaylistReorder {
+    fn run(&self, spotify: &Spotify) -> Result<()> {
+        anyhow::ensure!(self.from > 0 && self.before > 0, "Positions start at 1");
+
+        let id = playlist_id(spotify, &self.playlist)?;
+
+        spotify.reorder_playlist(&id, self.from - 1, self.length, self.before - 1)
+    }
+}
+
+impl PlaylistRename {
+    fn run(&self, spotify: &Spotify) -> Result<()> {
+        let id = playlist_id(spotify, &self.playlist)?;
+        spotify.rename_playlist(&id, &self.name)
+    }
+}
+
+impl PlaylistFollow {
+    fn run(&self, spotify: &Spotify) -> Result<()> {
+        let uri = match self.playlist.parse::<u32>() {
+            Ok(index) => crate::cache::lookup(index)?.uri.parse()?,
+            Err(_) => self.playlist.parse().unwrap_or_else(|_| SpotifyUri {
+                kind: ItemType::Playlist,
+                id: self.playlist.clone(),
+            }),
+        };
+
+        anyhow::ensure!(uri.kind == ItemType::Playlist, "{} is not a playlist", uri);
+
+        spotify.follow_playlist(&uri.id)
+    }
+}
+
+impl PlaylistUnfollow {
+    fn run(&self, spotify: &Spotify) -> Result<()> {
+        let id = playlist_id(spotify, &self.playlist)?;
+        spotify.unfollow_playlist(&id)
+    }
+}
+
+impl History {
+    fn run(&self, spotify: &mut LazySpotify, config: &mut Config, output: &Format) -> Result<()> {
+        anyhow::ensure!(
+            (1..=50).contains(&self.limit),
+            "--limit must be between 1 and 50"
+        );
+
+        let records: Vec<output::HistoryRecord> = spotify
+            .as_mut(config)?
+            .recently_played(self.limit, self.since)?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        output::list(output, &records)
+    }
+}
+
+impl TopList {
+    fn run<T>(&self, spotify: &Spotify, kind: &str, output: &Format) -> Result<()>
+    where
+        T: serde::de::DeserializeOwned + Into<crate::model::ItemSummary>,
+    {
+        let records: Vec<_> = self
+            .pages
+            .fetch(|limit, offset| spotify.top::<T>(kind, self.range.as_str(), limit, offset))?
+            .into_iter()
+            .map(|(rank, item)| output::TopRecord {
+                rank,
+                item: item.into(),
+            })
+            .collect();
+
+        output::list(output, &records)
     }
 }
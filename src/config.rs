@@ -1,17 +1,30 @@
-use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::io::{Read, Seek, SeekFrom};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 
 use crate::error::ApplicationError;
-use crate::log_err;
 use crate::Token;
 use anyhow::Result;
 use directories::ProjectDirs;
-use ring::aead::{Aad, LessSafeKey, Nonce};
+use fs2::FileExt;
+use ring::aead::{LessSafeKey, Nonce};
+use ring::rand::{SecureRandom, SystemRandom};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Format of the records written by `Encrypted::encrypt`.
+const RECORD_VERSION: u32 = 1;
 
 #[derive(Serialize, Deserialize)]
 struct Encrypted<T> {
+    /// `0` for records written before the format was versioned, which are not bound to their
+    /// client
+    #[serde(default)]
+    version: u32,
+
     #[serde(with = "serde_bytes")]
     nonce: Vec<u8>,
 
@@ -22,129 +35,340 @@ struct Encrypted<T> {
     _m: std::marker::PhantomData<T>,
 }
 
-struct ConfigSealingKey<'a> {
-    key: &'a LessSafeKey,
-    cfg: &'a mut Config,
+/// Client and field a record belongs to, authenticated as associated data so records can not be
+/// swapped between clients or fields.
+#[derive(Clone, Copy)]
+struct Binding<'a> {
+    client_id: &'a str,
+    field: &'static str,
 }
 
-fn advance_vec(bytes: &mut [u8]) -> Result<(), ApplicationError> {
-    if bytes.iter().all(|&b| b == 0xFF) {
-        return Err(ApplicationError::CryptographyError);
-    }
-
-    for byte in bytes.iter_mut().rev() {
-        if *byte == 0xFF {
-            *byte = 0;
-        } else {
-            *byte += 1;
-            break;
+impl<'a> Binding<'a> {
+    fn secret(client_id: &'a str) -> Self {
+        Self {
+            client_id,
+            field: "enc_secret",
         }
     }
 
-    Ok(())
-}
-
-impl<'a> ConfigSealingKey<'a> {
-    fn new(key: &'a LessSafeKey, cfg: &'a mut Config) -> Self {
-        Self { key, cfg }
+    fn token(client_id: &'a str) -> Self {
+        Self {
+            client_id,
+            field: "enc_token",
+        }
     }
 
-    fn advance_nonce(&mut self) -> Result<Vec<u8>, ApplicationError> {
-        self.cfg.dirty = true;
-        advance_vec(&mut self.cfg.nonce)?;
-
-        Ok(self.cfg.nonce.clone())
+    /// How the user replaces a record of the field that can not be used.
+    fn replace_hint(self) -> String {
+        match self.field {
+            "enc_token" => format!(
+                "eject the token with `spotr client eject {}` and log in again",
+                self.client_id
+            ),
+            _ => format!(
+                "remove the client with `spotr client remove {}` and add it again with \
+                 `spotr client new`",
+                self.client_id
+            ),
+        }
     }
 
-    pub fn seal_in_place_append_tag<A>(
-        &mut self,
-        aad: Aad<A>,
-        in_out: &mut Vec<u8>,
-    ) -> Result<Vec<u8>, ApplicationError>
-    where
-        A: AsRef<[u8]>,
-    {
-        let nonce = self.advance_nonce()?;
-
-        self.key.seal_in_place_append_tag(
-            Nonce::try_assume_unique_for_key(&nonce)?,
-            aad,
-            in_out,
-        )?;
-
-        Ok(nonce)
+    fn aad(self, version: u32) -> Vec<u8> {
+        match version {
+            0 => Vec::new(),
+            _ => format!("spotr:v{}:{}:{}", version, self.client_id, self.field).into_bytes(),
+        }
     }
 }
 
 impl<T> Encrypted<T> {
-    fn encrypt(value: &T, key: &mut ConfigSealingKey) -> Result<Self>
+    /// Encrypts `value` under a random nonce, which unlike a counter stays unique when several
+    /// processes encrypt at the same time.
+    fn encrypt(value: &T, key: &LessSafeKey, binding: Binding) -> Result<Self>
     where
         T: Serialize,
     {
         let mut data = serde_json::to_vec(value)?;
 
-        let nonce = key.seal_in_place_append_tag(ring::aead::Aad::empty(), &mut data)?;
+        let mut nonce = vec![0; ring::aead::NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(Into::<ApplicationError>::into)?;
+
+        key.seal_in_place_append_tag(
+            Nonce::try_assume_unique_for_key(&nonce).map_err(Into::<ApplicationError>::into)?,
+            ring::aead::Aad::from(binding.aad(RECORD_VERSION)),
+            &mut data,
+        )
+        .map_err(Into::<ApplicationError>::into)?;
 
         Ok(Self {
+            version: RECORD_VERSION,
             nonce,
             data,
             _m: std::marker::PhantomData,
         })
     }
 
-    fn decrypt(&self, key: &LessSafeKey) -> Result<T>
+    fn decrypt(&self, key: &LessSafeKey, binding: Binding) -> Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.open(key, binding, false)
+    }
+
+    /// Decrypts the record, accepting records that are not bound to their client only if
+    /// `unbound` is set, which is only the case while upgrading credentials that still use the
+    /// nonce counter.
+    fn open(&self, key: &LessSafeKey, binding: Binding, unbound: bool) -> Result<T>
     where
         T: serde::de::DeserializeOwned,
     {
+        anyhow::ensure!(
+            self.version <= RECORD_VERSION,
+            "The {} of client {} was written by a newer version of spotr",
+            binding.field,
+            binding.client_id
+        );
+
+        anyhow::ensure!(
+            self.version > 0 || unbound,
+            "The {} of client {} is not bound to its client, {}",
+            binding.field,
+            binding.client_id,
+            binding.replace_hint()
+        );
+
         let mut data = self.data.to_owned();
         let data = key
             .open_in_place(
                 ring::aead::Nonce::try_assume_unique_for_key(&self.nonce)
                     .map_err(Into::<ApplicationError>::into)?,
-                ring::aead::Aad::empty(),
+                ring::aead::Aad::from(binding.aad(self.version)),
                 &mut data,
             )
             .map_err(Into::<ApplicationError>::into)?;
 
         Ok(serde_json::from_slice(data)?)
     }
+
+    fn is_outdated(&self) -> bool {
+        self.version < RECORD_VERSION
+    }
+
+    /// Encrypts the record again under a fresh nonce in the current format.
+    fn reencrypt(&mut self, key: &LessSafeKey, binding: Binding, unbound: bool) -> Result<()>
+    where
+        T: Serialize + serde::de::DeserializeOwned,
+    {
+        *self = Self::encrypt(&self.open(key, binding, unbound)?, key, binding)?;
+
+        Ok(())
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-struct ClientData {
-    enc_secret: Encrypted<String>,
+/// Secret parts of a client, kept in the credentials store.
+#[derive(Serialize, Deserialize, Default)]
+struct ClientCredentials {
+    /// `None` for clients that authorize with PKCE
+    #[serde(default)]
+    enc_secret: Option<Encrypted<String>>,
+
+    #[serde(default)]
     enc_token: Option<Encrypted<Token>>,
 }
 
+/// Settings of a client that can be edited by hand.
 #[derive(Serialize, Deserialize, Default)]
-pub struct Config {
-    #[serde(with = "serde_bytes")]
-    nonce: Vec<u8>,
+struct ClientSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_device: Option<String>,
+
+    /// Redirect uris whitelisted for the client, tried in order when authorizing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    redirect_uris: Vec<String>,
+}
+
+/// Version of the credentials layout written by this version of spotr.
+const CONFIG_VERSION: u32 = 2;
+
+/// Version of the settings layout written by this version of spotr.
+const SETTINGS_VERSION: u32 = 1;
+
+/// Written at the top of the settings file, which loses everything toml does not keep.
+const SETTINGS_HEADER: &str = "\
+# Settings of spotr, which may be edited by hand.
+# spotr writes this file again when a command changes a setting, without keeping comments.
+
+";
 
-    default: Option<String>,
+/// Upgrades credentials from one version to the next, moving what is no longer part of them
+/// to the settings.
+type Migration = fn(&mut Map<String, Value>, &mut Settings) -> Result<()>;
 
-    clients: HashMap<String, ClientData>,
+/// Migrations of the credentials layout, the one at index `n` upgrades version `n` to `n + 1`.
+const MIGRATIONS: &[Migration] = &[from_unversioned, split_settings];
+
+/// Version 0 is the layout from before configs were versioned, whose fields added since all
+/// have defaults, so it only needs a version.
+fn from_unversioned(_config: &mut Map<String, Value>, _settings: &mut Settings) -> Result<()> {
+    Ok(())
+}
+
+/// Version 1 combined settings and credentials in one json file, version 2 moves the default
+/// client and the settings of each client to the settings.
+fn split_settings(config: &mut Map<String, Value>, settings: &mut Settings) -> Result<()> {
+    if let Some(Value::String(default)) = config.remove("default") {
+        settings.default_client = Some(default);
+        settings.dirty = true;
+    }
+
+    let clients = config
+        .get_mut("clients")
+        .and_then(Value::as_object_mut)
+        .into_iter()
+        .flatten();
+
+    for (id, client) in clients {
+        let client = match client.as_object_mut() {
+            Some(client) => client,
+            None => continue,
+        };
+
+        let client_settings = ClientSettings {
+            default_device: client
+                .remove("default_device")
+                .map(serde_json::from_value)
+                .transpose()?
+                .flatten(),
+            redirect_uris: client
+                .remove("redirect_uris")
+                .map(serde_json::from_value)
+                .transpose()?
+                .unwrap_or_default(),
+        };
+
+        if client_settings.default_device.is_some() || !client_settings.redirect_uris.is_empty() {
+            settings.clients.insert(id.to_owned(), client_settings);
+            settings.dirty = true;
+        }
+    }
+
+    Ok(())
+}
+
+/// Encrypted secrets and tokens of the registered clients, stored as json in the data dir.
+#[derive(Serialize, Deserialize, Default)]
+struct Credentials {
+    /// Layout version of the credentials, configs written before it was versioned are version 0
+    #[serde(default)]
+    version: u32,
+
+    /// Counter that records used to be encrypted with, only present in configs that have not
+    /// been re-encrypted with random nonces yet
+    #[serde(default, with = "serde_bytes", skip_serializing_if = "Vec::is_empty")]
+    nonce: Vec<u8>,
+
+    clients: HashMap<String, ClientCredentials>,
 
     #[serde(skip)]
     dirty: bool,
 
+    /// Whether the file holds valid credentials, which are kept as backup when replaced
+    #[serde(skip)]
+    keep_backup: bool,
+
+    /// Backup of the credentials taken by migrating them while reading, which is never pruned
+    /// in the same run so a copy of the old config survives the upgrade
+    #[serde(skip)]
+    migration_backup: Option<PathBuf>,
+}
+
+/// Preferences of the user, stored as toml in the config dir so they can be edited by hand.
+/// Commands that change a setting serialize the whole file again, so comments and formatting
+/// of hand edits are lost then, which [`SETTINGS_HEADER`] tells the user.
+#[derive(Serialize, Deserialize, Default)]
+struct Settings {
+    /// Layout version of the settings, settings written before it was versioned are version 0
+    #[serde(default)]
+    version: u32,
+
+    /// Client used when no client id is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_client: Option<String>,
+
+    /// Output format used when `--output` is not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+
+    /// Port to listen for the authorization redirect on when `--redirect-port` is not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    redirect_port: Option<u16>,
+
+    /// Commands the first argument expands to, e.g. `n = "next"`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    aliases: BTreeMap<String, String>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    clients: BTreeMap<String, ClientSettings>,
+
     #[serde(skip)]
-    path: std::path::PathBuf,
+    dirty: bool,
+}
+
+/// Settings and credentials of spotr. A client is registered when it has credentials, its
+/// settings are optional.
+#[derive(Default)]
+pub struct Config {
+    settings: Settings,
+    settings_path: PathBuf,
+
+    credentials: Credentials,
+    credentials_path: PathBuf,
+
+    /// Combined config the settings and credentials were migrated from, removed once they are
+    /// written to their own files
+    combined_path: Option<PathBuf>,
+
+    /// Advisory lock taken while reading the config and while writing it
+    lock_path: PathBuf,
+
+    /// Credentials and settings as they were read, to merge changes into files that another
+    /// command wrote in the meantime
+    credentials_read: Snapshot,
+    settings_read: Snapshot,
+}
+
+/// Content of a config file when it was read and the value parsed from it.
+#[derive(Default)]
+struct Snapshot {
+    content: String,
+    value: Value,
+}
+
+impl Snapshot {
+    fn new(path: &Path, value: &impl Serialize) -> Result<Self> {
+        Ok(Self {
+            content: read_content(path)?,
+            value: serde_json::to_value(value)?,
+        })
+    }
 }
 
 impl Config {
     pub fn set_default_force<'a>(&mut self, id: impl Into<std::borrow::Cow<'a, str>>) {
         let id = id.into();
 
-        self.dirty = true;
+        self.settings.dirty = true;
 
         match id {
             std::borrow::Cow::Owned(s) => {
-                self.default = Some(s);
+                self.settings.default_client = Some(s);
             }
             std::borrow::Cow::Borrowed(s) => {
                 let string = self
-                    .default
+                    .settings
+                    .default_client
                     .get_or_insert_with(|| String::with_capacity(s.len()));
 
                 string.truncate(0);
@@ -161,7 +385,7 @@ impl Config {
 
         let id = id.into();
 
-        if self.clients.contains_key(id.deref()) {
+        if self.has_client(id.deref()) {
             self.set_default_force(id);
             Ok(())
         } else {
@@ -170,41 +394,62 @@ impl Config {
     }
 
     pub fn default(&self) -> Option<&String> {
-        self.default.as_ref()
+        self.settings.default_client.as_ref()
     }
 
     pub fn clients(&self) -> impl Iterator<Item = (&String, bool)> {
-        self.clients
+        self.credentials
+            .clients
             .iter()
             .map(|(id, data)| (id, data.enc_token.is_some()))
     }
 
+    pub fn has_client(&self, id: &str) -> bool {
+        self.credentials.clients.contains_key(id)
+    }
+
     pub fn get_client_data(
         &self,
         id: &str,
         enc_key: &LessSafeKey,
-    ) -> Option<Result<(String, Option<Token>)>> {
-        let client = self.clients.get(id);
+    ) -> Option<Result<(Option<String>, Option<Token>)>> {
+        let secret = self.get_secret(id, enc_key)?;
+        let token = self.get_token(id, enc_key)?;
 
-        client.map(|client| {
-            let secret = client.enc_secret.decrypt(enc_key)?;
-            let token = client
-                .enc_token
+        Some(secret.and_then(|secret| Ok((secret, token?))))
+    }
+
+    /// Secret of a registered client, `None` inside for clients that authorize with PKCE.
+    pub fn get_secret(&self, id: &str, enc_key: &LessSafeKey) -> Option<Result<Option<String>>> {
+        let client = self.credentials.clients.get(id)?;
+
+        Some(
+            client
+                .enc_secret
                 .as_ref()
-                .map(|enc| enc.decrypt(enc_key))
-                .transpose()?;
+                .map(|enc| enc.decrypt(enc_key, Binding::secret(id)))
+                .transpose(),
+        )
+    }
 
-            Ok((secret, token))
-        })
+    /// Token of a registered client, `None` inside for clients that are not logged in.
+    pub fn get_token(&self, id: &str, enc_key: &LessSafeKey) -> Option<Result<Option<Token>>> {
+        let client = self.credentials.clients.get(id)?;
+
+        Some(
+            client
+                .enc_token
+                .as_ref()
+                .map(|enc| enc.decrypt(enc_key, Binding::token(id)))
+                .transpose(),
+        )
     }
 
     pub fn set_token(&mut self, id: &str, token: &Token, enc_key: &LessSafeKey) -> Result<()> {
-        self.dirty = true;
+        self.credentials.dirty = true;
 
-        if self.clients.contains_key(id) {
-            let enc_token = Encrypted::encrypt(token, &mut ConfigSealingKey::new(enc_key, self))?;
-
-            self.clients.get_mut(id).expect("is_some").enc_token = Some(enc_token);
+        if let Some(data) = self.credentials.clients.get_mut(id) {
+            data.enc_token = Some(Encrypted::encrypt(token, enc_key, Binding::token(id))?);
         } else {
             log::warn!("Attempting to set token on non-existing client id");
         }
@@ -213,29 +458,101 @@ impl Config {
     }
 
     pub fn eject_token(&mut self, id: &str) {
-        self.dirty = true;
+        self.credentials.dirty = true;
 
-        if let Some(data) = self.clients.get_mut(id) {
+        if let Some(data) = self.credentials.clients.get_mut(id) {
             data.enc_token = None;
         } else {
             log::warn!("Attempting to eject token on non-existing client id");
         }
     }
 
-    pub fn remove_client(&mut self, id: &str) {
-        self.dirty = true;
+    pub fn default_device(&self, id: &str) -> Option<&String> {
+        self.settings
+            .clients
+            .get(id)
+            .and_then(|data| data.default_device.as_ref())
+    }
+
+    pub fn set_default_device(&mut self, id: &str, device: Option<String>) {
+        if let Some(data) = self.client_settings(id) {
+            data.default_device = device;
+        } else {
+            log::warn!("Attempting to set default device on non-existing client id");
+        }
+    }
 
-        self.clients.remove(id);
+    pub fn redirect_uris(&self, id: &str) -> &[String] {
+        self.settings
+            .clients
+            .get(id)
+            .map(|data| data.redirect_uris.as_slice())
+            .unwrap_or_default()
     }
 
-    pub fn add_client(&mut self, id: String, secret: String, enc_key: &LessSafeKey) -> Result<()> {
-        self.dirty = true;
+    pub fn set_redirect_uris(&mut self, id: &str, redirect_uris: Vec<String>) {
+        if let Some(data) = self.client_settings(id) {
+            data.redirect_uris = redirect_uris;
+        } else {
+            log::warn!("Attempting to set redirect uris on non-existing client id");
+        }
+    }
+
+    /// Settings of a registered client to change, created if it has none yet.
+    fn client_settings(&mut self, id: &str) -> Option<&mut ClientSettings> {
+        if !self.has_client(id) {
+            return None;
+        }
+
+        self.settings.dirty = true;
+
+        Some(self.settings.clients.entry(id.to_owned()).or_default())
+    }
+
+    /// Output format to use when none is given on the command line.
+    pub fn output(&self) -> Option<&str> {
+        self.settings.output.as_deref()
+    }
+
+    /// Redirect port to use when none is given on the command line.
+    pub fn redirect_port(&self) -> Option<u16> {
+        self.settings.redirect_port
+    }
+
+    pub fn remove_client(&mut self, id: &str) {
+        self.credentials.dirty = true;
+        self.credentials.clients.remove(id);
 
-        let enc_secret = Encrypted::encrypt(&secret, &mut ConfigSealingKey::new(enc_key, self))?;
+        if self.settings.clients.remove(id).is_some() {
+            self.settings.dirty = true;
+        }
+    }
+
+    pub fn add_client(
+        &mut self,
+        id: String,
+        secret: Option<String>,
+        redirect_uris: Vec<String>,
+        enc_key: &LessSafeKey,
+    ) -> Result<()> {
+        self.credentials.dirty = true;
+        self.settings.dirty = true;
+
+        let enc_secret = secret
+            .map(|secret| Encrypted::encrypt(&secret, enc_key, Binding::secret(&id)))
+            .transpose()?;
+
+        self.settings.clients.insert(
+            id.clone(),
+            ClientSettings {
+                default_device: None,
+                redirect_uris,
+            },
+        );
 
-        self.clients.insert(
+        self.credentials.clients.insert(
             id,
-            ClientData {
+            ClientCredentials {
                 enc_secret,
                 enc_token: None,
             },
@@ -245,63 +562,937 @@ impl Config {
     }
 
     pub fn is_dirty(&self) -> bool {
-        self.dirty
+        self.settings.dirty || self.credentials.dirty
     }
 
-    pub fn write_if_dirty(self) -> anyhow::Result<()> {
-        if self.dirty {
-            let mut file = OpenOptions::new().write(true).open(&self.path)?;
+    /// Replaces the changed files through temporary files so they are never left half written,
+    /// the previous credentials are kept as backup. Files that another command wrote since they
+    /// were read get the changes of this command merged in instead.
+    pub fn write_if_dirty(mut self) -> anyhow::Result<()> {
+        if !self.is_dirty() {
+            return Ok(());
+        }
+
+        let _lock = lock(&self.lock_path, true)?;
+
+        if self.credentials.dirty {
+            if read_content(&self.credentials_path)? != self.credentials_read.content {
+                log::info!("credentials were written by another command, merging changes");
+
+                let mut fresh =
+                    serde_json::to_value(parse(&self.credentials_path, &mut Default::default())?)?;
+
+                merge(
+                    &self.credentials_read.value,
+                    &serde_json::to_value(&self.credentials)?,
+                    &mut fresh,
+                    2,
+                );
 
-            serde_json::to_writer(std::io::BufWriter::new(&mut file), &self)?;
+                self.credentials = Credentials {
+                    keep_backup: true,
+                    ..serde_json::from_value(fresh)?
+                };
+            }
+
+            self.credentials.version = CONFIG_VERSION;
+
+            write_atomic(
+                &self.credentials_path,
+                &serde_json::to_vec(&self.credentials)?,
+                self.credentials.keep_backup,
+            )?;
+
+            if !self.credentials.has_outdated_records() {
+                prune_unbound_backups(
+                    &self.credentials_path,
+                    self.credentials.migration_backup.as_deref(),
+                );
+            }
+        }
+
+        if self.settings.dirty {
+            if read_content(&self.settings_path)? != self.settings_read.content {
+                log::info!("settings were written by another command, merging changes");
+
+                let mut fresh = serde_json::to_value(read_settings(&self.settings_path)?)?;
+
+                merge(
+                    &self.settings_read.value,
+                    &serde_json::to_value(&self.settings)?,
+                    &mut fresh,
+                    2,
+                );
+
+                self.settings = serde_json::from_value(fresh)?;
+            }
+
+            self.settings.version = SETTINGS_VERSION;
 
-            let position = file.seek(SeekFrom::Current(0))?;
-            file.set_len(position)?;
+            write_atomic(
+                &self.settings_path,
+                (SETTINGS_HEADER.to_owned() + &toml::to_string_pretty(&self.settings)?).as_bytes(),
+                self.settings_path.exists(),
+            )?;
+        }
+
+        // Both are the same file on platforms where the config and data dirs are the same
+        match &self.combined_path {
+            Some(combined_path) if *combined_path != self.settings_path => {
+                match std::fs::remove_file(combined_path) {
+                    Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                        return Err(err.into())
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
         }
 
         Ok(())
     }
 }
 
-pub fn get() -> Option<Config> {
-    log_err!({
-        log::trace!("reading config");
+impl Credentials {
+    /// Whether the credentials used a nonce counter or have records in an older format.
+    /// Processes running at the same time could read the same counter and encrypt different
+    /// records under one nonce.
+    fn has_outdated_records(&self) -> bool {
+        !self.nonce.is_empty()
+            || self.clients.values().any(|client| {
+                client
+                    .enc_secret
+                    .as_ref()
+                    .is_some_and(|enc| enc.is_outdated())
+                    || client
+                        .enc_token
+                        .as_ref()
+                        .is_some_and(|enc| enc.is_outdated())
+            })
+    }
+
+    /// Re-encrypts outdated records under random nonces in the current format. The nonce
+    /// counter is only dropped once every record was re-encrypted, so failed records are tried
+    /// again next time. Records that are not bound to their client are only accepted from
+    /// credentials that still have the counter, as they were all written before binding.
+    fn upgrade_records(&mut self, enc_key: &LessSafeKey) {
+        log::info!("re-encrypting the config records");
+
+        let unbound = !self.nonce.is_empty();
+        let mut upgraded = true;
+
+        for (id, client) in &mut self.clients {
+            let result = client
+                .enc_secret
+                .iter_mut()
+                .try_for_each(|secret| secret.reencrypt(enc_key, Binding::secret(id), unbound))
+                .and_then(|_| {
+                    client
+                        .enc_token
+                        .iter_mut()
+                        .try_for_each(|token| token.reencrypt(enc_key, Binding::token(id), unbound))
+                });
+
+            if let Err(err) = result {
+                log::error!("could not re-encrypt client {}: {}", id, err);
+                upgraded = false;
+            }
+        }
+
+        if upgraded {
+            self.nonce.clear();
+        }
+
+        self.dirty = true;
+    }
+}
+
+fn project_dirs() -> Result<ProjectDirs> {
+    Ok(ProjectDirs::from("rs", "regiontog", "spotr")
+        .ok_or(ApplicationError::UnavailableConfigDir)?)
+}
+
+pub fn data_dir() -> Result<PathBuf> {
+    let path = project_dirs()?.data_dir().to_owned();
+    std::fs::create_dir_all(&path)?;
+
+    Ok(path)
+}
+
+pub fn config_dir() -> Result<PathBuf> {
+    let path = project_dirs()?.config_dir().to_owned();
+    std::fs::create_dir_all(&path)?;
+
+    Ok(path)
+}
+
+fn settings_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join("config.toml"))
+}
+
+/// Replaces an alias given as the first argument with the arguments it stands for.
+pub fn expand_alias(mut args: Vec<OsString>) -> Vec<OsString> {
+    let expansion = args.get(1).and_then(|arg| arg.to_str()).and_then(|alias| {
+        let settings = read_settings(&settings_path().ok()?).ok()?;
+        settings.aliases.get(alias).cloned()
+    });
+
+    if let Some(expansion) = expansion {
+        args.splice(1..2, expansion.split_whitespace().map(OsString::from));
+    }
+
+    args
+}
+
+/// Reads the config, `None` if it is unavailable. A config that exists but can not be parsed is
+/// an error rather than silently replaced.
+pub fn get() -> Result<Option<Config>> {
+    match read() {
+        Ok(config) => Ok(Some(config)),
+        Err(err)
+            if matches!(
+                err.downcast_ref(),
+                Some(ApplicationError::ConfigCorrupted { .. })
+                    | Some(ApplicationError::UnsupportedConfigVersion { .. })
+            ) =>
+        {
+            Err(err)
+        }
+        Err(err) => {
+            log::error!("{}", err);
+            Ok(None)
+        }
+    }
+}
+
+fn read() -> Result<Config> {
+    log::trace!("reading config");
+
+    let data_dir = data_dir()?;
+    let credentials_path = data_dir.join("credentials.json");
+    let settings_path = settings_path()?;
+
+    log::debug!("settings path: {:#?}", settings_path);
+    log::debug!("credentials path: {:#?}", credentials_path);
 
-        let dirs = ProjectDirs::from("rs", "regiontog", "spotr")
-            .ok_or(ApplicationError::UnavailableConfigDir)?;
+    let lock_path = data_dir.join("config.lock");
 
-        let mut path = dirs.data_dir().to_owned();
+    // Shared so commands only wait for others that are writing
+    let _lock = lock(&lock_path, false)?;
 
-        std::fs::create_dir_all(&path)?;
-        path.push("config.toml");
+    let combined_path = data_dir.join("config.toml");
 
-        log::debug!("config path: {:#?}", &path);
+    let combined_path = if !credentials_path.exists() && is_combined(&combined_path)? {
+        Some(combined_path)
+    } else {
+        None
+    };
 
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&path)?;
+    let mut settings = match combined_path {
+        // May be the same file as the combined config, which is replaced by the migration
+        Some(_) => Default::default(),
+        None => read_settings(&settings_path)?,
+    };
 
-        let len = file.metadata()?.len();
+    let mut credentials = match &combined_path {
+        Some(combined_path) => {
+            log::info!(
+                "moving settings to {} and credentials to {}",
+                settings_path.display(),
+                credentials_path.display()
+            );
 
-        let mut config: Config = if len > 0 {
-            let mut content = String::with_capacity(len as usize + 1);
-            file.read_to_string(&mut content)?;
+            let mut credentials = parse(combined_path, &mut settings)?;
 
-            serde_json::from_str(&content)?
+            // The combined config is kept as backup by the migration
+            credentials.keep_backup = false;
+            credentials.dirty = true;
+            settings.dirty = true;
+
+            credentials
+        }
+        None => match parse(&credentials_path, &mut settings) {
+            Ok(credentials) => credentials,
+            Err(err) => recover(&credentials_path, &mut settings, err)?,
+        },
+    };
+
+    if credentials.has_outdated_records() {
+        match crate::keyring::get_or_create_key() {
+            Ok(enc_key) => credentials.upgrade_records(&enc_key),
+            Err(err) => log::error!("could not re-encrypt the config: {}", err),
+        }
+    }
+
+    Ok(Config {
+        credentials_read: Snapshot::new(&credentials_path, &credentials)?,
+        settings_read: Snapshot::new(&settings_path, &settings)?,
+        settings,
+        settings_path,
+        credentials,
+        credentials_path,
+        combined_path,
+        lock_path,
+    })
+}
+
+/// Content of the file at `path`, empty if there is none.
+fn read_content(path: &Path) -> Result<String> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Applies the changes from `original` to `ours` onto `fresh`, which another command wrote
+/// since `original` was read. Objects are merged key by key down to `depth` levels, so changes
+/// to different clients or settings are kept, deeper values are replaced as a whole.
+fn merge(original: &Value, ours: &Value, fresh: &mut Value, depth: u32) {
+    if original == ours {
+        return;
+    }
+
+    if let (Value::Object(original), Value::Object(ours), Value::Object(fresh)) =
+        (original, ours, &mut *fresh)
+    {
+        if depth > 0 {
+            let keys: BTreeSet<&String> = original.keys().chain(ours.keys()).collect();
+
+            for key in keys {
+                match ours.get(key) {
+                    Some(value) => merge(
+                        original.get(key).unwrap_or(&Value::Null),
+                        value,
+                        fresh.entry(key.to_owned()).or_insert(Value::Null),
+                        depth - 1,
+                    ),
+                    None => {
+                        fresh.remove(key);
+                    }
+                }
+            }
+
+            return;
+        }
+    }
+
+    *fresh = ours.clone();
+}
+
+fn read_settings(path: &Path) -> Result<Settings> {
+    let content = read_content(path)?;
+
+    if content.is_empty() {
+        return Ok(Default::default());
+    }
+
+    let settings: Settings =
+        toml::from_str(&content).map_err(|err| ApplicationError::ConfigCorrupted {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        })?;
+
+    if settings.version > SETTINGS_VERSION {
+        return Err(ApplicationError::UnsupportedConfigVersion {
+            path: path.display().to_string(),
+            version: settings.version,
+            supported: SETTINGS_VERSION,
+        }
+        .into());
+    }
+
+    Ok(settings)
+}
+
+/// Whether `path` holds the json config that combined settings and credentials, which was named
+/// `config.toml` although it never was toml.
+fn is_combined(path: &Path) -> Result<bool> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(content.trim_start().starts_with('{')),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Takes the lock guarding the config, exclusive for writing it so concurrent invocations do
+/// not overwrite each other's changes, and shared for reading it so it is not read half
+/// written. It is only held while reading or writing, never while the command runs.
+fn lock(path: &Path, exclusive: bool) -> Result<File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+
+    // Called through the trait, as std has inherent methods of the same name on newer versions
+    let locked = if exclusive {
+        FileExt::try_lock_exclusive(&file)
+    } else {
+        FileExt::try_lock_shared(&file)
+    };
+
+    if locked.is_err() {
+        crate::dialouge::waiting_for_lock();
+
+        if exclusive {
+            FileExt::lock_exclusive(&file)?;
         } else {
-            log::info!("empty config file, using default");
-            Default::default()
+            FileExt::lock_shared(&file)?;
+        }
+    }
+
+    Ok(file)
+}
+
+/// Reads the credentials at `path`, migrating them to the current layout. Settings that older
+/// layouts kept with the credentials are moved to `settings`.
+fn parse(path: &Path, settings: &mut Settings) -> Result<Credentials> {
+    let content = read_content(path)?;
+
+    if content.is_empty() {
+        log::info!("no credentials, using default");
+        return Ok(Default::default());
+    }
+
+    let corrupted = |reason: String| ApplicationError::ConfigCorrupted {
+        path: path.display().to_string(),
+        reason,
+    };
+
+    let mut value = serde_json::from_str(&content).map_err(|err| corrupted(err.to_string()))?;
+
+    let version = match &mut value {
+        Value::Object(credentials) => migrate(path, credentials, settings)?,
+        _ => return Err(corrupted("expected an object".to_owned()).into()),
+    };
+
+    let mut credentials: Credentials =
+        serde_json::from_value(value).map_err(|err| corrupted(err.to_string()))?;
+
+    credentials.keep_backup = true;
+    credentials.dirty = version < CONFIG_VERSION;
+
+    if credentials.dirty {
+        credentials.migration_backup = Some(migration_backup(path, version));
+    }
+
+    Ok(credentials)
+}
+
+/// Upgrades `credentials` step by step to [`CONFIG_VERSION`], after copying the file at `path`
+/// to a backup. Returns the version the credentials were written with.
+fn migrate(
+    path: &Path,
+    credentials: &mut Map<String, Value>,
+    settings: &mut Settings,
+) -> Result<u32> {
+    let version = match credentials.get("version") {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| ApplicationError::ConfigCorrupted {
+                path: path.display().to_string(),
+                reason: format!("invalid version {}", version),
+            })?,
+    };
+
+    if version > CONFIG_VERSION {
+        return Err(ApplicationError::UnsupportedConfigVersion {
+            path: path.display().to_string(),
+            version,
+            supported: CONFIG_VERSION,
+        }
+        .into());
+    }
+
+    if version < CONFIG_VERSION {
+        let backup = migration_backup(path, version);
+
+        log::info!(
+            "migrating config from version {} to {}, the old config is backed up to {}",
+            version,
+            CONFIG_VERSION,
+            backup.display()
+        );
+
+        std::fs::copy(path, &backup)?;
+
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            log::debug!("migrating config from version {} to {}", from, from + 1);
+            migration(credentials, settings)?;
+        }
+
+        credentials.insert("version".to_owned(), CONFIG_VERSION.into());
+    }
+
+    Ok(version)
+}
+
+/// Offers to restore the backup of credentials that could not be parsed.
+fn recover(path: &Path, settings: &mut Settings, err: anyhow::Error) -> Result<Credentials> {
+    let backup = sibling(path, ".bak");
+
+    let corrupted = matches!(
+        err.downcast_ref(),
+        Some(ApplicationError::ConfigCorrupted { .. })
+    );
+
+    if !corrupted || !backup.exists() {
+        return Err(err);
+    }
+
+    log::error!("{}", err);
+
+    let mut credentials = match parse(&backup, settings) {
+        Ok(credentials) => credentials,
+        Err(backup_err) => {
+            log::error!("backup is not usable either: {}", backup_err);
+            return Err(err);
+        }
+    };
+
+    if !crate::dialouge::restore_backup(&backup)? {
+        return Err(err);
+    }
+
+    // The corrupted credentials must not replace the backup they were restored from
+    credentials.keep_backup = false;
+    credentials.dirty = true;
+
+    Ok(credentials)
+}
+
+/// Removes backups of the credentials at `path`, or of the combined config they were migrated
+/// from, that hold records not bound to their client, which could be swapped between clients if
+/// restored, once the credentials are upgraded. The backup at `keep` is left for a later run.
+fn prune_unbound_backups(path: &Path, keep: Option<&Path>) {
+    let sources = vec![path.to_owned(), path.with_file_name("config.toml")];
+
+    let backups = sources.into_iter().flat_map(|source| {
+        std::iter::once(sibling(&source, ".bak"))
+            .chain((0..CONFIG_VERSION).map(move |version| migration_backup(&source, version)))
+    });
+
+    for backup in backups.filter(|backup| Some(backup.as_path()) != keep) {
+        let unbound = match std::fs::read_to_string(&backup) {
+            Ok(content) => has_unbound_records(&content),
+            Err(_) => continue,
         };
 
-        config.path = path;
+        if unbound {
+            log::info!("removing backup with unbound records {}", backup.display());
 
-        if config.nonce.is_empty() {
-            config.nonce.extend(&[0; ring::aead::NONCE_LEN]);
+            if let Err(err) = std::fs::remove_file(&backup) {
+                log::error!("could not remove {}: {}", backup.display(), err);
+            }
         }
+    }
+}
+
+/// Whether the credentials in `content` have records from before they were bound to their
+/// client.
+fn has_unbound_records(content: &str) -> bool {
+    let credentials: Value = match serde_json::from_str(content) {
+        Ok(credentials) => credentials,
+        Err(_) => return false,
+    };
+
+    let clients = match credentials.get("clients").and_then(Value::as_object) {
+        Some(clients) => clients,
+        None => return false,
+    };
+
+    clients
+        .values()
+        .flat_map(|client| vec![client.get("enc_secret"), client.get("enc_token")])
+        .flatten()
+        .filter(|record| record.is_object())
+        .any(|record| record.get("version").and_then(Value::as_u64).unwrap_or(0) == 0)
+}
 
-        assert_eq!(ring::aead::NONCE_LEN, config.nonce.len());
+/// Backup of the credentials at `path` taken before migrating them from `version`.
+fn migration_backup(path: &Path, version: u32) -> PathBuf {
+    sibling(path, &format!(".v{}.bak", version))
+}
 
-        Ok(config)
-    })
+/// `path` with `suffix` appended to its file name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(suffix);
+
+    path.with_file_name(name)
+}
+
+/// Replaces the file at `path` with `contents` through a temporary file, copying the file it
+/// replaces to a backup first if `keep_backup`.
+fn write_atomic(path: &Path, contents: &[u8], keep_backup: bool) -> Result<()> {
+    let tmp = sibling(path, ".tmp");
+
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    writer.write_all(contents)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+
+    if keep_backup {
+        std::fs::copy(path, sibling(path, ".bak"))?;
+    }
+
+    std::fs::rename(&tmp, path)?;
+
+    if let Some(dir) = path.parent() {
+        sync_dir(dir)?;
+    }
+
+    Ok(())
+}
+
+/// Makes a rename in `dir` durable, which is only possible on unix.
+fn sync_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+
+    #[cfg(not(unix))]
+    let _ = dir;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ring::aead::{Aad, UnboundKey};
+
+    fn key() -> LessSafeKey {
+        LessSafeKey::new(UnboundKey::new(crate::CRYPT_ALGO, &[7; 32]).unwrap())
+    }
+
+    /// Empty directory for a test, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("spotr-{}-{}", name, std::process::id()));
+
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Record as written with the nonce counter, before records were bound to their client.
+    fn counter_record(value: &str, counter: u8) -> Value {
+        let mut nonce = vec![0; ring::aead::NONCE_LEN];
+        *nonce.last_mut().unwrap() = counter;
+
+        let mut data = serde_json::to_vec(value).unwrap();
+        key()
+            .seal_in_place_append_tag(
+                Nonce::try_assume_unique_for_key(&nonce).unwrap(),
+                Aad::empty(),
+                &mut data,
+            )
+            .unwrap();
+
+        serde_json::json!({ "nonce": nonce, "data": data })
+    }
+
+    #[test]
+    fn interleaved_writers_use_distinct_nonces() {
+        let dir = TempDir::new("interleaved");
+        let path = dir.0.join("credentials.json");
+
+        std::fs::write(
+            &path,
+            serde_json::json!({ "version": CONFIG_VERSION, "nonce": [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], "clients": {} })
+                .to_string(),
+        )
+        .unwrap();
+
+        // Both read the same file before either writes, as two processes without a lock would
+        let mut first = Config {
+            credentials: parse(&path, &mut Settings::default()).unwrap(),
+            ..Default::default()
+        };
+        let mut second = Config {
+            credentials: parse(&path, &mut Settings::default()).unwrap(),
+            ..Default::default()
+        };
+
+        first
+            .add_client("a".to_owned(), Some("first".to_owned()), vec![], &key())
+            .unwrap();
+        second
+            .add_client("a".to_owned(), Some("second".to_owned()), vec![], &key())
+            .unwrap();
+
+        let nonce = |config: &Config| {
+            config.credentials.clients["a"]
+                .enc_secret
+                .as_ref()
+                .unwrap()
+                .nonce
+                .clone()
+        };
+
+        assert_ne!(nonce(&first), nonce(&second));
+
+        let secret = |config: &Config| config.get_client_data("a", &key()).unwrap().unwrap().0;
+
+        assert_eq!(secret(&first).as_deref(), Some("first"));
+        assert_eq!(secret(&second).as_deref(), Some("second"));
+    }
+
+    #[test]
+    fn counter_records_are_reencrypted() {
+        let dir = TempDir::new("counter");
+        let path = dir.0.join("credentials.json");
+
+        let old = counter_record("secret", 1);
+
+        std::fs::write(
+            &path,
+            serde_json::json!({
+                "nonce": [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+                "clients": { "a": { "enc_secret": old, "enc_token": null } },
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let mut credentials = parse(&path, &mut Settings::default()).unwrap();
+        assert!(credentials.has_outdated_records());
+
+        credentials.upgrade_records(&key());
+
+        assert!(!credentials.has_outdated_records());
+        assert!(credentials.nonce.is_empty());
+
+        let secret = credentials.clients["a"].enc_secret.as_ref().unwrap();
+
+        assert_eq!(secret.version, RECORD_VERSION);
+        assert_ne!(Value::from(secret.nonce.clone()), old["nonce"]);
+        assert_eq!(
+            secret.decrypt(&key(), Binding::secret("a")).unwrap(),
+            "secret"
+        );
+    }
+
+    #[test]
+    fn counter_is_kept_until_every_record_is_reencrypted() {
+        let mut credentials: Credentials = serde_json::from_value(serde_json::json!({
+            "nonce": [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2],
+            "clients": {
+                "a": { "enc_secret": counter_record("secret", 1) },
+                "b": { "enc_secret": { "nonce": [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], "data": [1, 2, 3] } },
+            },
+        }))
+        .unwrap();
+
+        credentials.upgrade_records(&key());
+
+        assert!(credentials.has_outdated_records());
+        assert!(!credentials.nonce.is_empty());
+    }
+
+    #[test]
+    fn unbound_records_are_refused_once_upgraded() {
+        let mut credentials: Credentials = serde_json::from_value(serde_json::json!({
+            "clients": { "a": { "enc_secret": counter_record("secret", 1) } },
+        }))
+        .unwrap();
+
+        credentials.upgrade_records(&key());
+
+        let secret = credentials.clients["a"].enc_secret.as_ref().unwrap();
+
+        assert!(secret.is_outdated());
+        assert!(secret.decrypt(&key(), Binding::secret("a")).is_err());
+    }
+
+    #[test]
+    fn backups_with_unbound_records_are_pruned() {
+        let dir = TempDir::new("prune");
+        let path = dir.0.join("credentials.json");
+
+        let unbound = serde_json::json!({
+            "clients": { "a": { "enc_secret": counter_record("secret", 1) } },
+        })
+        .to_string();
+
+        let mut bound = Credentials::default();
+        bound.clients.insert(
+            "a".to_owned(),
+            ClientCredentials {
+                enc_secret: Some(
+                    Encrypted::encrypt(&"secret".to_owned(), &key(), Binding::secret("a")).unwrap(),
+                ),
+                enc_token: None,
+            },
+        );
+        let bound = serde_json::to_string(&bound).unwrap();
+
+        std::fs::write(sibling(&path, ".v0.bak"), &unbound).unwrap();
+        std::fs::write(dir.0.join("config.toml.v1.bak"), &unbound).unwrap();
+        std::fs::write(sibling(&path, ".bak"), &bound).unwrap();
+        std::fs::write(sibling(&path, ".v1.bak"), &unbound).unwrap();
+
+        prune_unbound_backups(&path, Some(&sibling(&path, ".v1.bak")));
+
+        assert!(sibling(&path, ".v1.bak").exists());
+        assert!(!sibling(&path, ".v0.bak").exists());
+        assert!(!dir.0.join("config.toml.v1.bak").exists());
+        assert!(sibling(&path, ".bak").exists());
+    }
+
+    fn parse_fixture(name: &str, content: &str) -> (TempDir, PathBuf, Result<Credentials>) {
+        let dir = TempDir::new(name);
+        let path = dir.0.join("credentials.json");
+
+        std::fs::write(&path, content).unwrap();
+        let credentials = parse(&path, &mut Settings::default());
+
+        (dir, path, credentials)
+    }
+
+    #[test]
+    fn unversioned_credentials_are_migrated() {
+        let content = r#"{"nonce":[0,0,0,0,0,0,0,0,0,0,0,1],"clients":{"a":{"enc_secret":null}}}"#;
+        let (_dir, path, credentials) = parse_fixture("unversioned", content);
+        let credentials = credentials.unwrap();
+
+        assert_eq!(credentials.version, CONFIG_VERSION);
+        assert!(credentials.dirty);
+        assert!(credentials.clients.contains_key("a"));
+        assert_eq!(
+            std::fs::read_to_string(sibling(&path, ".v0.bak")).unwrap(),
+            content
+        );
+    }
+
+    #[test]
+    fn current_credentials_are_kept() {
+        let content = format!(r#"{{"version":{},"clients":{{}}}}"#, CONFIG_VERSION);
+        let (_dir, path, credentials) = parse_fixture("current", &content);
+        let credentials = credentials.unwrap();
+
+        assert_eq!(credentials.version, CONFIG_VERSION);
+        assert!(!credentials.dirty);
+        assert!(!sibling(&path, &format!(".v{}.bak", CONFIG_VERSION)).exists());
+    }
+
+    #[test]
+    fn newer_credentials_are_unsupported() {
+        let content = format!(r#"{{"version":{},"clients":{{}}}}"#, CONFIG_VERSION + 1);
+        let (_dir, _, credentials) = parse_fixture("newer", &content);
+
+        assert!(matches!(
+            credentials.err().unwrap().downcast_ref(),
+            Some(ApplicationError::UnsupportedConfigVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_versions_are_corrupted() {
+        let (_dir, _, credentials) = parse_fixture("invalid", r#"{"version":"1","clients":{}}"#);
+
+        assert!(matches!(
+            credentials.err().unwrap().downcast_ref(),
+            Some(ApplicationError::ConfigCorrupted { .. })
+        ));
+    }
+
+    #[test]
+    fn combined_settings_are_split() {
+        let dir = TempDir::new("combined");
+        let path = dir.0.join("config.toml");
+
+        std::fs::write(
+            &path,
+            r#"{"version":1,"default":"a","clients":{"a":{"enc_secret":null,"default_device":"d","redirect_uris":["http://localhost:8888"]},"b":{"enc_secret":null}}}"#,
+        )
+        .unwrap();
+
+        let mut settings = Settings::default();
+        let credentials = parse(&path, &mut settings).unwrap();
+
+        assert_eq!(credentials.version, CONFIG_VERSION);
+        assert_eq!(credentials.clients.len(), 2);
+        assert!(sibling(&path, ".v1.bak").exists());
+
+        assert!(settings.dirty);
+        assert_eq!(settings.default_client.as_deref(), Some("a"));
+        assert_eq!(settings.clients.len(), 1);
+        assert_eq!(settings.clients["a"].default_device.as_deref(), Some("d"));
+        assert_eq!(
+            settings.clients["a"].redirect_uris,
+            vec!["http://localhost:8888".to_owned()]
+        );
+
+        let written = serde_json::to_value(&credentials).unwrap();
+
+        assert!(written.get("default").is_none());
+        assert!(written["clients"]["a"].get("default_device").is_none());
+    }
+
+    /// Config read from `dir` as `read` does.
+    fn load(dir: &Path) -> Config {
+        let credentials_path = dir.join("credentials.json");
+        let settings_path = dir.join("config.toml");
+
+        let mut settings = read_settings(&settings_path).unwrap();
+        let credentials = parse(&credentials_path, &mut settings).unwrap();
+
+        Config {
+            credentials_read: Snapshot::new(&credentials_path, &credentials).unwrap(),
+            settings_read: Snapshot::new(&settings_path, &settings).unwrap(),
+            settings,
+            settings_path,
+            credentials,
+            credentials_path,
+            combined_path: None,
+            lock_path: dir.join("config.lock"),
+        }
+    }
+
+    #[test]
+    fn concurrent_changes_are_merged() {
+        let dir = TempDir::new("merge");
+
+        let mut first = load(&dir.0);
+        let mut second = load(&dir.0);
+
+        first
+            .add_client("a".to_owned(), None, vec![], &key())
+            .unwrap();
+        first.set_default_force("a");
+
+        second
+            .add_client(
+                "b".to_owned(),
+                None,
+                vec!["http://localhost:1".to_owned()],
+                &key(),
+            )
+            .unwrap();
+
+        first.write_if_dirty().unwrap();
+        second.write_if_dirty().unwrap();
+
+        let merged = load(&dir.0);
+
+        assert!(merged.has_client("a"));
+        assert!(merged.has_client("b"));
+        assert_eq!(merged.default().map(String::as_str), Some("a"));
+        assert_eq!(merged.redirect_uris("b"), ["http://localhost:1"]);
+    }
 }
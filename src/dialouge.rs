@@ -1,11 +1,19 @@
 use std::io;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 
 use anyhow::Result;
 
+/// Asks on stderr so prompts do not end up in the output of scripts, which are answered no as
+/// they can not answer.
 pub fn confirm(prompt: &str) -> Result<bool> {
-    write!(io::stdout(), ":: {}? [Y/n] ", prompt)?;
-    io::stdout().flush()?;
+    write!(io::stderr(), ":: {}? [Y/n] ", prompt)?;
+
+    if !io::stdin().is_terminal() {
+        writeln!(io::stderr(), "n")?;
+        return Ok(false);
+    }
+
+    io::stderr().flush()?;
 
     let mut input = String::new();
     io::stdin().read_line(&mut input)?;
@@ -15,36 +23,85 @@ pub fn confirm(prompt: &str) -> Result<bool> {
     Ok(input == "" || input == "y" || input == "Y")
 }
 
-pub fn new_client() -> Result<(String, String)> {
+pub fn new_client() -> Result<(String, Option<String>, Vec<String>)> {
     writeln!(
-        io::stdout(),
+        io::stderr(),
         "To use this CLI application you need to register an application with spotify. \
         You can register an application at 'https://developer.spotify.com/dashboard/applications'. \
         It does not matter what you choose for name, description or application type. \
-        When you have created the application click edit settings and add \
-        'http://localhost:9524' to the redirect whitelist."
+        When you have created the application click edit settings and add one or more redirect \
+        uris such as '{}' to the redirect whitelist, spotr listens on the first one whose port \
+        is available. \
+        The client secret is optional, leave it empty to authorize without it so the \
+        client id can be shared without sharing the secret.",
+        crate::oauth::DEFAULT_REDIRECT_URI
     )?;
 
-    write!(io::stdout(), ":: Client id? ")?;
+    write!(io::stderr(), ":: Client id? ")?;
 
-    io::stdout().flush()?;
+    io::stderr().flush()?;
 
     let mut id = String::new();
     io::stdin().read_line(&mut id)?;
 
     id = id.trim().to_owned();
 
-    let secret = rpassword::read_password_from_tty(Some(":: Client secret? "))?
+    let secret = rpassword::read_password_from_tty(Some(":: Client secret (optional)? "))?
         .trim()
         .to_owned();
 
-    Ok((id, secret))
+    write!(
+        io::stderr(),
+        ":: Redirect uris, separated by spaces? [{}] ",
+        crate::oauth::DEFAULT_REDIRECT_URI
+    )?;
+
+    io::stderr().flush()?;
+
+    let mut redirect_uris = String::new();
+    io::stdin().read_line(&mut redirect_uris)?;
+
+    let redirect_uris = redirect_uris
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect();
+
+    Ok((
+        id,
+        Some(secret).filter(|secret| !secret.is_empty()),
+        redirect_uris,
+    ))
 }
 
-pub fn set_default() -> Result<bool> {
-    confirm("Set new client as default")
+pub fn redirected_url() -> Result<String> {
+    write!(io::stderr(), ":: Redirected url or authorization code? ")?;
+    io::stderr().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+
+    Ok(input.trim().to_owned())
+}
+
+pub fn authorize_scopes(scopes: &[&str]) -> Result<bool> {
+    confirm(&format!(
+        "This command needs permission for {}, authorize again",
+        scopes.join(", ")
+    ))
+}
+
+pub fn restore_backup(backup: &std::path::Path) -> Result<bool> {
+    confirm(&format!(
+        "The config could not be read, restore the backup at {}",
+        backup.display()
+    ))
+}
+
+/// Announced on stderr so it does not end up in the output of scripts.
+pub fn waiting_for_lock() {
+    eprintln!(":: Waiting for another spotr command to finish writing the config");
 }
 
-pub fn display(value: &str) -> Result<()> {
-    Ok(writeln!(io::stdout(), "{}", value)?)
+pub fn set_default() -> Result<bool> {
+    confirm("Set new client as default")
 }
@@ -9,6 +9,119 @@ pub enum ApplicationError {
     UnavailableConfigDir,
     #[error("An error occured during crypto operatation")]
     CryptographyError,
+    #[error("Spotify responded with status {status}: {message}")]
+    SpotifyApi { status: u16, message: String },
+    #[error("No {0} matching '{1}'")]
+    NoMatch(&'static str, String),
+    #[error("The {0} '{1}' is ambiguous, it matches: {2}")]
+    Ambiguous(&'static str, String, String),
+    #[error("No result with index {0} in the last search")]
+    NoSuchResult(u32),
+    #[error("Authorization failed with '{error}': {description}")]
+    Authorization { error: String, description: String },
+    #[error("Authorization response does not belong to this login attempt, try again")]
+    StateMismatch,
+    #[error("Gave up waiting for authorization after {0} seconds")]
+    AuthorizationTimeout(u64),
+    #[error("Authorization was cancelled")]
+    AuthorizationCancelled,
+    #[error(
+        "Could not listen for the authorization redirect on any of {0}, the ports may be in use \
+        by another program. Whitelist another redirect uri and add it with 'spotr client redirect'"
+    )]
+    RedirectUnavailable(String),
+    #[error("The client is not authorized for {0}, authorize it again with 'spotr login'")]
+    MissingScopes(String),
+    #[error(
+        "No active device, start playback on a device or pick one with --device, see 'spotr device list'"
+    )]
+    NoActiveDevice,
+    #[error("Nothing is playing, start playback or name the item to use")]
+    NothingPlaying,
+    #[error("This requires Spotify Premium: {0}")]
+    PremiumRequired(String),
+    #[error("Not found: {0}, check the id or uri")]
+    NotFound(String),
+    #[error("Spotify rejected the access token: {0}, log in again with 'spotr login'")]
+    Unauthorized(String),
+    #[error(
+        "Rate limited by spotify{}, try again later or pass a higher --max-attempts",
+        retry_after(*.0)
+    )]
+    RateLimited(Option<u64>),
+    #[error("Could not reach spotify: {0}, check your network connection")]
+    Network(String),
+    #[error("The config at {path} is corrupted ({reason}), fix or remove it")]
+    ConfigCorrupted { path: String, reason: String },
+    #[error(
+        "The config at {path} has version {version} but this spotr only supports up to version \
+        {supported}, upgrade spotr"
+    )]
+    UnsupportedConfigVersion {
+        path: String,
+        version: u32,
+        supported: u32,
+    },
+}
+
+/// Exit codes of the errors scripts are likely to handle, these are stable across releases.
+pub const EXIT_CODES_HELP: &str = "EXIT CODES:
+    1    any other error
+    3    no active device or nothing playing
+    4    spotify premium required
+    5    item, device or search result not found
+    6    not authorized, log in again
+    7    rate limited
+    8    network failure
+    9    corrupted config or config of a newer spotr
+    130  cancelled while waiting for authorization";
+
+impl ApplicationError {
+    /// Exit code of the error, see [`EXIT_CODES_HELP`].
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::NoActiveDevice | Self::NothingPlaying => 3,
+            Self::PremiumRequired(_) => 4,
+            Self::NotFound(_) | Self::NoMatch(..) | Self::NoSuchResult(_) => 5,
+            Self::Unauthorized(_)
+            | Self::MissingScopes(_)
+            | Self::Authorization { .. }
+            | Self::StateMismatch
+            | Self::AuthorizationTimeout(_) => 6,
+            Self::RateLimited(_) => 7,
+            Self::Network(_) => 8,
+            Self::ConfigCorrupted { .. } | Self::UnsupportedConfigVersion { .. } => 9,
+            Self::AuthorizationCancelled => 130,
+            _ => 1,
+        }
+    }
+}
+
+fn retry_after(secs: Option<u64>) -> String {
+    secs.map(|secs| format!(" for {} seconds", secs))
+        .unwrap_or_default()
+}
+
+/// Exit code for an error, that of the first [`ApplicationError`] it was caused by.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<ApplicationError>() {
+            return err.exit_code();
+        }
+
+        if let Some(err) = cause.downcast_ref::<ArcAnyhowError>() {
+            return exit_code(&err.0);
+        }
+
+        if let Some(attohttpc::ErrorKind::Io(_)) = cause
+            .downcast_ref::<attohttpc::Error>()
+            .map(attohttpc::Error::kind)
+        {
+            return 8;
+        }
+    }
+
+    1
 }
 
 impl From<ring::error::Unspecified> for ApplicationError {
@@ -6,19 +6,40 @@ use serde::{Deserialize, Serialize};
 use spotify_web::scope::*;
 use structopt::StructOpt;
 
+mod api;
+mod args;
+mod cache;
 mod cli;
 mod config;
 mod dialouge;
 mod error;
 mod keyring;
+mod model;
 mod oauth;
+mod output;
+mod scopes;
 
-type Scope = spotify_web::scopes![UserReadCurrentlyPlaying, UserModifyPlaybackState];
+/// Scopes of the spotify_web clients used for exchanging tokens, the scopes that are actually
+/// requested are decided per command, see [`scopes`].
+type Scope = spotify_web::scopes![
+    UserReadCurrentlyPlaying,
+    UserReadPlaybackState,
+    UserModifyPlaybackState,
+    UserLibraryRead,
+    UserLibraryModify,
+    PlaylistReadPrivate,
+    PlaylistReadCollaborative,
+    PlaylistModifyPublic,
+    PlaylistModifyPrivate,
+    UserReadRecentlyPlayed,
+    UserTopRead,
+    UserReadPrivate
+];
 
-static CRYPT_ALGO: &ring::aead::Algorithm = &ring::aead::AES_256_GCM;
+/// Tokens are refreshed this many seconds before they expire.
+const EXPIRY_MARGIN_SECS: i64 = 60;
 
-// TODO
-// * devices
+static CRYPT_ALGO: &ring::aead::Algorithm = &ring::aead::AES_256_GCM;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Token {
@@ -26,23 +47,36 @@ pub struct Token {
 
     #[serde(with = "ts_seconds")]
     expires_at: chrono::DateTime<chrono::Utc>,
+
+    /// Scopes the token was granted
+    #[serde(default = "scopes::legacy")]
+    scopes: Vec<String>,
 }
 
 impl Token {
-    fn new(inner: spotify_web::model::Token) -> Self {
+    fn new(inner: spotify_web::model::Token, scopes: Vec<String>) -> Self {
         Self {
             expires_at: chrono::Utc::now() + chrono::Duration::seconds(inner.expires_in),
             token: inner,
+            scopes,
         }
     }
 
+    /// Whether the token has expired or is about to, so it does not expire mid command.
     fn has_expired(&self) -> bool {
-        chrono::Utc::now() >= self.expires_at
+        chrono::Utc::now() + chrono::Duration::seconds(EXPIRY_MARGIN_SECS) >= self.expires_at
+    }
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {:?}", err);
+        std::process::exit(error::exit_code(&err));
     }
 }
 
-fn main() -> Result<()> {
-    let cli = cli::CLI::from_args();
+fn run() -> Result<()> {
+    let cli = cli::CLI::from_iter(config::expand_alias(std::env::args_os().collect()));
 
     Builder::from_default_env()
         .format_timestamp(None)
@@ -56,21 +90,28 @@ fn main() -> Result<()> {
         })
         .init();
 
-    let mut config = config::get();
+    let mut config = config::get()?;
 
-    if let Some(config) = config.as_mut() {
-        cli.run(config)?;
+    let result = if let Some(config) = config.as_mut() {
+        cli.run(config)
     } else {
         let mut tmp_cfg = Default::default();
-        cli.run(&mut tmp_cfg)?;
 
-        anyhow::ensure!(
-            !tmp_cfg.is_dirty(),
-            "Could not read config but config was changed!"
-        );
-    }
+        cli.run(&mut tmp_cfg).and_then(|_| {
+            anyhow::ensure!(
+                !tmp_cfg.is_dirty(),
+                "Could not read config but config was changed!"
+            );
+
+            Ok(())
+        })
+    };
+
+    // Written even when the command failed, as tokens refreshed or authorized before the
+    // failure would be lost otherwise
+    let written = config.map(|c| c.write_if_dirty()).unwrap_or(Ok(()));
 
-    config.map(|c| c.write_if_dirty()).unwrap_or(Ok(()))
+    result.and(written)
 }
 
 #[macro_export]
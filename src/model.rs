@@ -0,0 +1,439 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Debug)]
+pub struct ErrorResponse {
+    pub error: ErrorObject,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ErrorObject {
+    pub message: String,
+
+    /// Set by the player endpoints, e.g. `NO_ACTIVE_DEVICE` or `PREMIUM_REQUIRED`
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Device {
+    pub id: Option<String>,
+    pub name: String,
+
+    #[serde(rename = "type")]
+    pub kind: String,
+
+    pub volume_percent: Option<u32>,
+    pub is_active: bool,
+
+    #[serde(default)]
+    pub is_restricted: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Devices {
+    pub devices: Vec<Device>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SimpleArtist {
+    pub name: String,
+    pub uri: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SimpleAlbum {
+    pub name: String,
+    pub uri: String,
+
+    #[serde(default)]
+    pub artists: Vec<SimpleArtist>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SimpleShow {
+    pub name: String,
+    pub publisher: String,
+    pub uri: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Track {
+    pub name: String,
+    pub uri: String,
+    pub duration_ms: u64,
+    pub artists: Vec<SimpleArtist>,
+    pub album: SimpleAlbum,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Episode {
+    pub name: String,
+    pub uri: String,
+    pub duration_ms: u64,
+    pub show: SimpleShow,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SimpleEpisode {
+    pub name: String,
+    pub uri: String,
+    pub duration_ms: u64,
+    pub release_date: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PlayingItem {
+    Track(Track),
+    Episode(Episode),
+}
+
+impl PlayingItem {
+    pub fn duration_ms(&self) -> u64 {
+        match self {
+            Self::Track(track) => track.duration_ms,
+            Self::Episode(episode) => episode.duration_ms,
+        }
+    }
+}
+
+impl From<PlayingItem> for ItemSummary {
+    fn from(item: PlayingItem) -> Self {
+        match item {
+            PlayingItem::Track(track) => track.into(),
+            PlayingItem::Episode(episode) => Self {
+                kind: ItemType::Episode,
+                by: Some(episode.show.name),
+                name: episode.name,
+                uri: episode.uri,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayingType {
+    Track,
+    Episode,
+    Ad,
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlaybackState {
+    pub device: Option<Device>,
+    pub shuffle_state: bool,
+    pub repeat_state: String,
+    pub progress_ms: Option<u64>,
+    pub is_playing: bool,
+    pub currently_playing_type: PlayingType,
+    pub item: Option<PlayingItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemType {
+    Track,
+    Album,
+    Artist,
+    Playlist,
+    Show,
+    Episode,
+}
+
+impl ItemType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Track => "track",
+            Self::Album => "album",
+            Self::Artist => "artist",
+            Self::Playlist => "playlist",
+            Self::Show => "show",
+            Self::Episode => "episode",
+        }
+    }
+
+    /// Whether items of this type are played as a context rather than as a single uri.
+    pub fn is_context(self) -> bool {
+        !matches!(self, Self::Track | Self::Episode)
+    }
+}
+
+impl std::str::FromStr for ItemType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "track" => Ok(Self::Track),
+            "album" => Ok(Self::Album),
+            "artist" => Ok(Self::Artist),
+            "playlist" => Ok(Self::Playlist),
+            "show" => Ok(Self::Show),
+            "episode" => Ok(Self::Episode),
+            _ => Err(anyhow::anyhow!("Unknown item type '{}'", s)),
+        }
+    }
+}
+
+/// A `spotify:<type>:<id>` uri, also parsed from `https://open.spotify.com/<type>/<id>` urls.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpotifyUri {
+    pub kind: ItemType,
+    pub id: String,
+}
+
+impl std::fmt::Display for SpotifyUri {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "spotify:{}:{}", self.kind.as_str(), self.id)
+    }
+}
+
+impl std::str::FromStr for SpotifyUri {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let parts: Vec<_> = if let Some(uri) = s.strip_prefix("spotify:") {
+            uri.split(':').collect()
+        } else if let Some(path) = s
+            .strip_prefix("https://open.spotify.com/")
+            .or_else(|| s.strip_prefix("http://open.spotify.com/"))
+            .or_else(|| s.strip_prefix("open.spotify.com/"))
+        {
+            path.split(['?', '#'])
+                .next()
+                .unwrap_or("")
+                .split('/')
+                .filter(|part| !part.starts_with("intl-"))
+                .collect()
+        } else {
+            anyhow::bail!("'{}' is not a spotify uri or url", s);
+        };
+
+        // Legacy playlist uris are prefixed by their owner, e.g. spotify:user:<user>:playlist:<id>
+        let parts = match parts.as_slice() {
+            ["user", _, rest @ ..] if !rest.is_empty() => rest,
+            parts => parts,
+        };
+
+        match parts {
+            [kind, id] if !id.is_empty() => Ok(Self {
+                kind: kind.parse()?,
+                id: (*id).to_owned(),
+            }),
+            _ => Err(anyhow::anyhow!("'{}' is not a spotify uri or url", s)),
+        }
+    }
+}
+
+fn skip_null<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    let items: Vec<Option<T>> = Deserialize::deserialize(deserializer)?;
+    Ok(items.into_iter().flatten().collect())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Paging<T> {
+    #[serde(
+        deserialize_with = "skip_null",
+        bound(deserialize = "T: Deserialize<'de>")
+    )]
+    pub items: Vec<T>,
+
+    pub total: u32,
+    pub limit: u32,
+    pub offset: u32,
+    pub next: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PublicUser {
+    pub id: String,
+    pub display_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TracksRef {
+    pub total: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SimplePlaylist {
+    pub id: String,
+    pub name: String,
+    pub uri: String,
+    pub owner: PublicUser,
+    pub tracks: TracksRef,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct SearchResults {
+    pub tracks: Option<Paging<Track>>,
+    pub albums: Option<Paging<SimpleAlbum>>,
+    pub artists: Option<Paging<SimpleArtist>>,
+    pub playlists: Option<Paging<SimplePlaylist>>,
+    pub shows: Option<Paging<SimpleShow>>,
+    pub episodes: Option<Paging<SimpleEpisode>>,
+}
+
+/// Common view of any kind of item.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ItemSummary {
+    #[serde(rename = "type")]
+    pub kind: ItemType,
+
+    pub name: String,
+
+    /// Artists, owner or publisher of the item
+    pub by: Option<String>,
+
+    pub uri: String,
+}
+
+pub fn artist_names(artists: &[SimpleArtist]) -> String {
+    artists
+        .iter()
+        .map(|a| a.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl From<Track> for ItemSummary {
+    fn from(track: Track) -> Self {
+        Self {
+            kind: ItemType::Track,
+            by: Some(artist_names(&track.artists)),
+            name: track.name,
+            uri: track.uri,
+        }
+    }
+}
+
+impl From<SimpleAlbum> for ItemSummary {
+    fn from(album: SimpleAlbum) -> Self {
+        Self {
+            kind: ItemType::Album,
+            by: Some(artist_names(&album.artists)),
+            name: album.name,
+            uri: album.uri,
+        }
+    }
+}
+
+impl From<SimpleArtist> for ItemSummary {
+    fn from(artist: SimpleArtist) -> Self {
+        Self {
+            kind: ItemType::Artist,
+            by: None,
+            name: artist.name,
+            uri: artist.uri,
+        }
+    }
+}
+
+impl From<SimplePlaylist> for ItemSummary {
+    fn from(playlist: SimplePlaylist) -> Self {
+        Self {
+            kind: ItemType::Playlist,
+            by: Some(playlist.owner.display_name.unwrap_or(playlist.owner.id)),
+            name: playlist.name,
+            uri: playlist.uri,
+        }
+    }
+}
+
+impl From<SimpleShow> for ItemSummary {
+    fn from(show: SimpleShow) -> Self {
+        Self {
+            kind: ItemType::Show,
+            by: Some(show.publisher),
+            name: show.name,
+            uri: show.uri,
+        }
+    }
+}
+
+impl From<SimpleEpisode> for ItemSummary {
+    fn from(episode: SimpleEpisode) -> Self {
+        Self {
+            kind: ItemType::Episode,
+            by: None,
+            name: episode.name,
+            uri: episode.uri,
+        }
+    }
+}
+
+impl SearchResults {
+    /// All results in the order tracks, albums, artists, playlists, shows and episodes.
+    pub fn into_items(self) -> Vec<ItemSummary> {
+        fn items<T: Into<ItemSummary>>(
+            paging: Option<Paging<T>>,
+        ) -> impl Iterator<Item = ItemSummary> {
+            paging.into_iter().flat_map(|p| p.items).map(Into::into)
+        }
+
+        items(self.tracks)
+            .chain(items(self.albums))
+            .chain(items(self.artists))
+            .chain(items(self.playlists))
+            .chain(items(self.shows))
+            .chain(items(self.episodes))
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Queue {
+    pub currently_playing: Option<PlayingItem>,
+
+    #[serde(deserialize_with = "skip_null")]
+    pub queue: Vec<PlayingItem>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Saved<T> {
+    pub added_at: chrono::DateTime<chrono::Utc>,
+
+    #[serde(alias = "track", alias = "album", alias = "show", alias = "episode")]
+    pub item: T,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PrivateUser {
+    pub id: String,
+    pub display_name: Option<String>,
+
+    /// Only present with the user-read-private scope
+    pub country: Option<String>,
+
+    /// Subscription level, e.g. premium or free, only present with the user-read-private scope
+    pub product: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PlaylistItem {
+    pub added_at: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// `None` for items that are no longer available
+    pub track: Option<PlayingItem>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Context {
+    pub uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PlayHistory {
+    pub track: Track,
+    pub played_at: chrono::DateTime<chrono::Utc>,
+    pub context: Option<Context>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CursorPaging<T> {
+    pub items: Vec<T>,
+}
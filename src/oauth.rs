@@ -1,38 +1,403 @@
+use std::collections::HashMap;
 use std::io;
 use std::io::Write;
-use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
-use crate::error::RouilleError;
-use anyhow::Result;
+use crate::error::{ApplicationError, RouilleError};
+use anyhow::{anyhow, Result};
 use parking_lot::Mutex;
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use spotify_web::model::Token;
 
-pub fn code(url: &str) -> Result<String> {
-    let code = Arc::new(Mutex::new(None));
-    let code2 = code.clone();
+/// How long to block waiting for a redirect between polls of the callback server.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
 
-    let server = rouille::Server::new("localhost:9524", move |request| {
-        *code2.lock() = Some(
-            request
-                .get_param("code")
-                .expect("Spotify should provide code as a url query parameter"),
-        );
+type CallbackResult = std::result::Result<String, ApplicationError>;
 
-        rouille::Response::text("Close me!").with_status_code(404)
-    })
-    .map_err(Into::<RouilleError>::into)?;
+/// Redirect uri used by clients that have not whitelisted any others.
+pub const DEFAULT_REDIRECT_URI: &str = "http://localhost:9524";
 
-    let open = open::that(&url);
+type Handler = Box<dyn Fn(&rouille::Request) -> rouille::Response + Send + Sync>;
 
-    if open.is_err() {
-        writeln!(io::stdout(), "Open '{}' to authorize with spotify", url)?;
+/// How the result of an authorization gets back to spotr.
+pub enum Redirect {
+    /// Spotify redirects to a local server.
+    Listen(Listener),
+
+    /// The user pastes the url spotify redirected to, for when the browser runs on another
+    /// machine.
+    Paste { redirect_uri: String, state: String },
+}
+
+impl Redirect {
+    pub fn listen(redirect_uris: &[String]) -> Result<Self> {
+        Listener::bind(redirect_uris).map(Self::Listen)
     }
 
-    loop {
-        server.poll();
-        {
-            if let Some(code) = &*code.lock() {
-                return Ok(code.to_owned());
+    pub fn paste(redirect_uri: &str) -> Result<Self> {
+        Ok(Self::Paste {
+            redirect_uri: redirect_uri.to_owned(),
+            state: random_string(16)?,
+        })
+    }
+
+    pub fn redirect_uri(&self) -> &str {
+        match self {
+            Self::Listen(listener) => &listener.redirect_uri,
+            Self::Paste { redirect_uri, .. } => redirect_uri,
+        }
+    }
+
+    /// Sends the user to `url` to authorize and returns the resulting authorization code.
+    pub fn code(self, mut url: url::Url, timeout: Duration) -> Result<String> {
+        match self {
+            Self::Listen(listener) => listener.code(url, timeout),
+            Self::Paste { state, .. } => {
+                url.query_pairs_mut().append_pair("state", &state);
+
+                writeln!(
+                    io::stderr(),
+                    "Open '{}' in a browser to authorize with spotify. Afterwards the browser is \
+                    redirected to a page that likely fails to load, copy its url from the \
+                    address bar.",
+                    url
+                )?;
+
+                pasted_code(&crate::dialouge::redirected_url()?, &state)
+            }
+        }
+    }
+}
+
+/// Local server that spotify redirects to with the result of an authorization.
+pub struct Listener {
+    server: rouille::Server<Handler>,
+    redirect_uri: String,
+    state: String,
+    sender: mpsc::Sender<CallbackResult>,
+    receiver: mpsc::Receiver<CallbackResult>,
+}
+
+impl Listener {
+    /// Listens on the first of `redirect_uris` whose port is available.
+    pub fn bind(redirect_uris: &[String]) -> Result<Self> {
+        let state = random_string(16)?;
+        let (sender, receiver) = mpsc::channel();
+
+        for redirect_uri in redirect_uris {
+            let url = url::Url::parse(redirect_uri)
+                .map_err(|err| anyhow!("Invalid redirect uri '{}': {}", redirect_uri, err))?;
+
+            let addr = format!(
+                "{}:{}",
+                url.host_str().unwrap_or("localhost"),
+                url.port_or_known_default().unwrap_or(80)
+            );
+
+            let handler = callback(url.path().to_owned(), state.clone(), sender.clone());
+
+            match rouille::Server::new(&addr, handler) {
+                Ok(server) => {
+                    log::debug!("listening for redirect to '{}'", redirect_uri);
+
+                    return Ok(Self {
+                        server,
+                        redirect_uri: redirect_uri.to_owned(),
+                        state,
+                        sender,
+                        receiver,
+                    });
+                }
+                Err(err) => log::info!(
+                    "could not listen on {}: {}",
+                    addr,
+                    Into::<RouilleError>::into(err)
+                ),
+            }
+        }
+
+        Err(ApplicationError::RedirectUnavailable(redirect_uris.join(", ")).into())
+    }
+
+    /// Waits for spotify to redirect back with an authorization code, giving up after `timeout`
+    /// or when interrupted with Ctrl-C.
+    pub fn code(self, mut url: url::Url, timeout: Duration) -> Result<String> {
+        url.query_pairs_mut().append_pair("state", &self.state);
+
+        let open = open::that(url.as_str());
+
+        if open.is_err() {
+            writeln!(
+                io::stderr(),
+                "Open '{}' to authorize with spotify, or use --no-browser if the browser runs on \
+                another machine",
+                url
+            )?;
+        }
+
+        cancel_on_ctrlc(self.sender);
+
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            self.server.poll();
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining == Duration::from_secs(0) {
+                return Err(ApplicationError::AuthorizationTimeout(timeout.as_secs()).into());
+            }
+
+            match self.receiver.recv_timeout(remaining.min(POLL_INTERVAL)) {
+                Ok(result) => return Ok(result?),
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    unreachable!("callback holds a sender")
+                }
+            }
+        }
+    }
+}
+
+fn callback(path: String, state: String, sender: mpsc::Sender<CallbackResult>) -> Handler {
+    let sender = Mutex::new(sender);
+
+    Box::new(move |request| {
+        if request.url() != path {
+            return rouille::Response::empty_404();
+        }
+
+        match redirect_result(|name| request.get_param(name), &state) {
+            Some(Ok(code)) => {
+                let _ = sender.lock().send(Ok(code));
+
+                page(
+                    200,
+                    "Authorized",
+                    "spotr is now authorized, you can close this window.",
+                )
             }
+            Some(Err(err)) => {
+                let response = page(400, "Authorization failed", &err.to_string());
+                let _ = sender.lock().send(Err(err));
+
+                response
+            }
+            None => rouille::Response::empty_404(),
+        }
+    })
+}
+
+fn cancel_on_ctrlc(sender: mpsc::Sender<CallbackResult>) {
+    let handler = ctrlc::set_handler(move || {
+        // Nobody is waiting for authorization anymore, exit like the default handler would
+        if sender
+            .send(Err(ApplicationError::AuthorizationCancelled))
+            .is_err()
+        {
+            std::process::exit(130);
         }
+    });
+
+    if let Err(err) = handler {
+        log::warn!("could not install ctrl-c handler: {}", err);
+    }
+}
+
+/// Replaces the scopes requested by an authorization url.
+pub fn set_scopes(url: &mut url::Url, scopes: &[String]) {
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .into_owned()
+        .filter(|(key, _)| key != "scope")
+        .collect();
+
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair("scope", &scopes.join(" "));
+}
+
+/// Extracts the code from the query parameters of a redirect, `None` if they are not from an
+/// authorization redirect at all.
+fn redirect_result(param: impl Fn(&str) -> Option<String>, state: &str) -> Option<CallbackResult> {
+    let result = match (param("code"), param("error")) {
+        (None, None) => return None,
+        (_, Some(error)) => Err(ApplicationError::Authorization {
+            error,
+            description: param("error_description").unwrap_or_default(),
+        }),
+        (Some(code), None) => Ok(code),
+    };
+
+    if param("state").as_deref() != Some(state) {
+        return Some(Err(ApplicationError::StateMismatch));
+    }
+
+    Some(result)
+}
+
+/// Extracts the code from a pasted redirect url, or takes the input as the code itself.
+fn pasted_code(input: &str, state: &str) -> Result<String> {
+    let input = input.trim();
+
+    match url::Url::parse(input) {
+        Ok(url) => {
+            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+            let result =
+                redirect_result(|name| params.get(name).cloned(), state).ok_or_else(|| {
+                    anyhow!("The url has no authorization code, copy it after authorizing")
+                })?;
+
+            Ok(result?)
+        }
+        Err(_) => {
+            anyhow::ensure!(!input.is_empty(), "No authorization code given");
+
+            Ok(input.to_owned())
+        }
+    }
+}
+
+fn page(status: u16, title: &str, message: &str) -> rouille::Response {
+    rouille::Response::html(format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>spotr</title></head>\
+        <body><h1>{}</h1><p>{}</p></body></html>",
+        title,
+        escape_html(message)
+    ))
+    .with_status_code(status)
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Url safe random string made from `len` random bytes.
+fn random_string(len: usize) -> Result<String> {
+    let mut bytes = vec![0; len];
+
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(Into::<ApplicationError>::into)?;
+
+    Ok(base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
+
+const TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+
+/// Proof key for code exchange, lets clients without a secret authorize.
+pub struct Pkce {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl Pkce {
+    pub fn new() -> Result<Self> {
+        let verifier = random_string(64)?;
+        let challenge = base64::encode_config(
+            &digest::digest(&digest::SHA256, verifier.as_bytes()),
+            base64::URL_SAFE_NO_PAD,
+        );
+
+        Ok(Self {
+            verifier,
+            challenge,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenError {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Exchanges an authorization code for a token without a client secret.
+pub fn fetch_token(
+    client_id: &str,
+    code: &str,
+    redirect_uri: &str,
+    verifier: &str,
+) -> Result<Token> {
+    request_token(&[
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", redirect_uri),
+        ("client_id", client_id),
+        ("code_verifier", verifier),
+    ])
+}
+
+/// Refreshes a token obtained with [`fetch_token`].
+pub fn refresh_token(client_id: &str, token: &Token) -> Result<Token> {
+    let refresh = token
+        .refresh_token
+        .clone()
+        .ok_or_else(|| anyhow!("Token can not be refreshed, log in again"))?;
+
+    let mut token = request_token(&[
+        ("grant_type", "refresh_token"),
+        ("refresh_token", &refresh),
+        ("client_id", client_id),
+    ])?;
+
+    // Spotify only includes a refresh token when it has been rotated
+    if token.refresh_token.is_some() {
+        log::debug!("refresh token was rotated");
+    } else {
+        token.refresh_token = Some(refresh);
+    }
+
+    Ok(token)
+}
+
+fn request_token(form: &[(&str, &str)]) -> Result<Token> {
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(form)
+        .finish();
+
+    let response = attohttpc::post(TOKEN_URL)
+        .header(
+            attohttpc::header::CONTENT_TYPE,
+            "application/x-www-form-urlencoded",
+        )
+        .text(body)
+        .send()?;
+
+    let status = response.status();
+    let bytes = response.bytes()?;
+
+    if status.is_success() {
+        return Ok(serde_json::from_slice(&bytes)?);
+    }
+
+    Err(match serde_json::from_slice::<TokenError>(&bytes) {
+        Ok(error) => ApplicationError::Authorization {
+            error: error.error,
+            description: error.error_description.unwrap_or_default(),
+        },
+        Err(_) => ApplicationError::SpotifyApi {
+            status: status.as_u16(),
+            message: String::from_utf8_lossy(&bytes).into_owned(),
+        },
     }
+    .into())
 }
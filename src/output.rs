@@ -0,0 +1,719 @@
+//! Rendering of command results.
+//!
+//! Every command that produces output does so through a [`Record`], which can be rendered in
+//! one of the [`Format`]s selected with `--output`. The json and yaml formats serialize the
+//! records as-is, a list command yields an array while `status` and `whoami` yield a single
+//! object. The tsv format prints one line per record with the record's fields in order, and
+//! templates substitute `{field}` with the value of that field for each record.
+//!
+//! The fields of each command are listed in the help of `--output`, see [`COMMAND_FIELDS`].
+//!
+//! Missing values are `null` in json and yaml and empty in tsv and templates.
+
+use std::io::{self, Write};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::model::{self, PlaybackState, PlayingItem, PlayingType};
+
+/// Fields of the records of each command that produces output.
+pub const COMMAND_FIELDS: &[(&str, &[&str])] = &[
+    ("client list", ClientRecord::FIELDS),
+    ("whoami", UserRecord::FIELDS),
+    ("device list", DeviceRecord::FIELDS),
+    ("status", StatusRecord::FIELDS),
+    ("search", SearchRecord::FIELDS),
+    ("queue list", QueueRecord::FIELDS),
+    ("library", AddedRecord::FIELDS),
+    ("playlist show", AddedRecord::FIELDS),
+    ("playlist list and create", PlaylistRecord::FIELDS),
+    ("library contains", SavedRecord::FIELDS),
+    ("history", HistoryRecord::FIELDS),
+    ("top", TopRecord::FIELDS),
+];
+
+/// Help of `--output`, listing the fields of each command.
+pub fn format_help() -> &'static str {
+    let fields: Vec<_> = COMMAND_FIELDS
+        .iter()
+        .map(|(command, fields)| format!("{}: {}", command, fields.join(", ")))
+        .collect();
+
+    // clap keeps borrowing the help for as long as the program runs
+    Box::leak(
+        format!(
+            "Output format, one of human, json, yaml, tsv or template=FORMAT where FORMAT \
+            substitutes {{field}} with the fields of each result, e.g. \
+            'template={{artist}} - {{title}}'. Fields per command are: {}",
+            fields.join("; ")
+        )
+        .into_boxed_str(),
+    )
+}
+
+const PROGRESS_WIDTH: usize = 30;
+
+#[derive(Debug, Clone)]
+pub enum Format {
+    Human,
+    Json,
+    Yaml,
+    Tsv,
+    Template(String),
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            "tsv" => Ok(Self::Tsv),
+            _ => match s.strip_prefix("template=") {
+                Some(template) => Ok(Self::Template(template.to_owned())),
+                None => Err(anyhow!(
+                    "Unknown output format '{}', expected human, json, yaml, tsv or template=FORMAT",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// A single result of a command.
+pub trait Record: Serialize {
+    /// Fields in tsv column order, also the fields available to templates.
+    const FIELDS: &'static [&'static str];
+
+    fn human(&self, out: &mut dyn Write) -> Result<()>;
+}
+
+/// Renders the result of a command that yields a single record.
+pub fn one<R: Record>(format: &Format, record: &R) -> Result<()> {
+    let mut out = io::stdout();
+
+    match format {
+        Format::Human => record.human(&mut out),
+        Format::Json => json(&mut out, record),
+        Format::Yaml => yaml(&mut out, &serde_json::to_value(record)?, 0),
+        Format::Tsv => tsv(&mut out, record),
+        Format::Template(template) => self::template(&mut out, template, record),
+    }
+}
+
+/// Renders the result of a command that yields a list of records.
+pub fn list<R: Record>(format: &Format, records: &[R]) -> Result<()> {
+    let mut out = io::stdout();
+
+    match format {
+        Format::Json => json(&mut out, &records),
+        Format::Yaml => yaml(&mut out, &serde_json::to_value(records)?, 0),
+        _ => records.iter().try_for_each(|record| one(format, record)),
+    }
+}
+
+fn json(out: &mut dyn Write, value: &impl Serialize) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *out, value)?;
+    Ok(writeln!(out)?)
+}
+
+fn yaml_scalar(value: &Value) -> Result<String> {
+    Ok(match value {
+        Value::Array(a) if a.is_empty() => "[]".to_owned(),
+        Value::Object(o) if o.is_empty() => "{}".to_owned(),
+        // Json strings are valid double quoted yaml scalars
+        _ => serde_json::to_string(value)?,
+    })
+}
+
+fn is_block(value: &Value) -> bool {
+    match value {
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(o) => !o.is_empty(),
+        _ => false,
+    }
+}
+
+fn yaml(out: &mut dyn Write, value: &Value, indent: usize) -> Result<()> {
+    let pad = " ".repeat(indent);
+
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                if is_block(value) {
+                    writeln!(out, "{}{}:", pad, key)?;
+                    yaml(out, value, indent + 2)?;
+                } else {
+                    writeln!(out, "{}{}: {}", pad, key, yaml_scalar(value)?)?;
+                }
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for item in items {
+                if is_block(item) {
+                    // Render the item one level deeper and put the dash in the first line's indent
+                    let mut buf = Vec::new();
+                    yaml(&mut buf, item, indent + 2)?;
+
+                    let item = String::from_utf8(buf)?;
+                    write!(out, "{}- {}", pad, &item[indent + 2..])?;
+                } else {
+                    writeln!(out, "{}- {}", pad, yaml_scalar(item)?)?;
+                }
+            }
+        }
+        _ => writeln!(out, "{}{}", pad, yaml_scalar(value)?)?,
+    }
+
+    Ok(())
+}
+
+fn field(value: &Value, name: &str) -> String {
+    match value.get(name) {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+fn tsv<R: Record>(out: &mut dyn Write, record: &R) -> Result<()> {
+    let value = serde_json::to_value(record)?;
+
+    let line = R::FIELDS
+        .iter()
+        .map(|name| field(&value, name).replace(['\t', '\n'], " "))
+        .collect::<Vec<_>>()
+        .join("\t");
+
+    Ok(writeln!(out, "{}", line)?)
+}
+
+fn template<R: Record>(out: &mut dyn Write, template: &str, record: &R) -> Result<()> {
+    let value = serde_json::to_value(record)?;
+    let mut line = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        line.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let end = rest
+            .find('}')
+            .ok_or(anyhow!("Unclosed '{{' in output template"))?;
+        let name = &rest[..end];
+
+        anyhow::ensure!(
+            R::FIELDS.contains(&name),
+            "Unknown field '{}' in output template, available fields are: {}",
+            name,
+            R::FIELDS.join(", ")
+        );
+
+        line.push_str(&field(&value, name));
+        rest = &rest[end + 1..];
+    }
+
+    line.push_str(rest);
+
+    Ok(writeln!(out, "{}", line)?)
+}
+
+#[derive(Serialize)]
+pub struct ClientRecord<'a> {
+    pub id: &'a str,
+    pub token: bool,
+    pub default: bool,
+}
+
+impl Record for ClientRecord<'_> {
+    const FIELDS: &'static [&'static str] = &["id", "token", "default"];
+
+    fn human(&self, out: &mut dyn Write) -> Result<()> {
+        Ok(writeln!(
+            out,
+            "{:<33}{}{}",
+            self.id,
+            if self.token { "[token]" } else { "       " },
+            if self.default { "[default]" } else { "" },
+        )?)
+    }
+}
+
+#[derive(Serialize)]
+pub struct UserRecord<'a> {
+    pub client: &'a str,
+    pub id: String,
+    pub display_name: Option<String>,
+    pub country: Option<String>,
+    pub product: Option<String>,
+}
+
+impl<'a> UserRecord<'a> {
+    pub fn new(client: &'a str, user: model::PrivateUser) -> Self {
+        Self {
+            client,
+            id: user.id,
+            display_name: user.display_name,
+            country: user.country,
+            product: user.product,
+        }
+    }
+}
+
+impl Record for UserRecord<'_> {
+    const FIELDS: &'static [&'static str] = &["client", "id", "display_name", "country", "product"];
+
+    fn human(&self, out: &mut dyn Write) -> Result<()> {
+        writeln!(
+            out,
+            "{} ({})",
+            self.display_name.as_deref().unwrap_or(&self.id),
+            self.id
+        )?;
+
+        if let Some(country) = &self.country {
+            writeln!(out, "country: {}", country)?;
+        }
+
+        if let Some(product) = &self.product {
+            writeln!(out, "product: {}", product)?;
+        }
+
+        Ok(writeln!(out, "client: {}", self.client)?)
+    }
+}
+
+#[derive(Serialize)]
+pub struct DeviceRecord {
+    pub id: Option<String>,
+    pub name: String,
+
+    #[serde(rename = "type")]
+    pub kind: String,
+
+    pub volume: Option<u32>,
+    pub active: bool,
+    pub default: bool,
+}
+
+impl DeviceRecord {
+    pub fn new(device: model::Device, default: Option<&String>) -> Self {
+        Self {
+            default: device.id.is_some() && device.id.as_ref() == default,
+            id: device.id,
+            name: device.name,
+            kind: device.kind,
+            volume: device.volume_percent,
+            active: device.is_active,
+        }
+    }
+}
+
+impl Record for DeviceRecord {
+    const FIELDS: &'static [&'static str] = &["id", "name", "type", "volume", "active", "default"];
+
+    fn human(&self, out: &mut dyn Write) -> Result<()> {
+        Ok(writeln!(
+            out,
+            "{:<41}{:<25}{:<12}{:>4}{}{}",
+            self.id.as_deref().unwrap_or(""),
+            self.name,
+            self.kind,
+            self.volume.map(|v| format!("{}%", v)).unwrap_or_default(),
+            if self.active { "[active]" } else { "        " },
+            if self.default { "[default]" } else { "" },
+        )?)
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerState {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+/// Flattened playback state, tracks and episodes share fields where for episodes `artist` is
+/// the show's publisher and `album` the show's name.
+#[derive(Serialize)]
+pub struct StatusRecord {
+    pub state: PlayerState,
+
+    #[serde(rename = "type")]
+    pub kind: Option<PlayingType>,
+
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub uri: Option<String>,
+    pub progress_ms: Option<u64>,
+    pub duration_ms: Option<u64>,
+    pub shuffle: Option<bool>,
+    pub repeat: Option<String>,
+    pub device: Option<String>,
+
+    #[serde(skip)]
+    device_kind: Option<String>,
+}
+
+impl StatusRecord {
+    pub fn new(state: Option<PlaybackState>) -> Self {
+        let mut record = Self {
+            state: PlayerState::Stopped,
+            kind: None,
+            artist: None,
+            title: None,
+            album: None,
+            uri: None,
+            progress_ms: None,
+            duration_ms: None,
+            shuffle: None,
+            repeat: None,
+            device: None,
+            device_kind: None,
+        };
+
+        let state = match state {
+            Some(state) => state,
+            None => return record,
+        };
+
+        record.state = if state.is_playing {
+            PlayerState::Playing
+        } else {
+            PlayerState::Paused
+        };
+
+        record.kind = Some(state.currently_playing_type);
+        record.progress_ms = state.progress_ms;
+        record.shuffle = Some(state.shuffle_state);
+        record.repeat = Some(state.repeat_state);
+
+        if let Some(device) = state.device {
+            record.device = Some(device.name);
+            record.device_kind = Some(device.kind);
+        }
+
+        match state.item {
+            Some(PlayingItem::Track(track)) => {
+                record.artist = Some(model::artist_names(&track.artists));
+                record.title = Some(track.name);
+                record.album = Some(track.album.name);
+                record.uri = Some(track.uri);
+                record.duration_ms = Some(track.duration_ms);
+            }
+            Some(PlayingItem::Episode(episode)) => {
+                record.artist = Some(episode.show.publisher);
+                record.title = Some(episode.name);
+                record.album = Some(episode.show.name);
+                record.uri = Some(episode.uri);
+                record.duration_ms = Some(episode.duration_ms);
+            }
+            None => {}
+        }
+
+        record
+    }
+}
+
+fn duration(ms: u64) -> String {
+    let secs = ms / 1000;
+
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+fn progress_bar(progress: u64, total: u64) -> String {
+    let filled = (progress.min(total) * PROGRESS_WIDTH as u64)
+        .checked_div(total)
+        .unwrap_or(0) as usize;
+
+    format!(
+        "[{}{}]",
+        "#".repeat(filled),
+        "-".repeat(PROGRESS_WIDTH - filled)
+    )
+}
+
+impl Record for StatusRecord {
+    const FIELDS: &'static [&'static str] = &[
+        "state",
+        "type",
+        "artist",
+        "title",
+        "album",
+        "uri",
+        "progress_ms",
+        "duration_ms",
+        "shuffle",
+        "repeat",
+        "device",
+    ];
+
+    fn human(&self, out: &mut dyn Write) -> Result<()> {
+        if self.state == PlayerState::Stopped {
+            return Ok(writeln!(out, "Nothing is playing")?);
+        }
+
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => writeln!(out, "{} - {}", artist, title)?,
+            _ if self.kind == Some(PlayingType::Ad) => writeln!(out, "Advertisement")?,
+            _ => writeln!(out, "Unknown item")?,
+        }
+
+        if let Some(album) = &self.album {
+            writeln!(out, "{}", album)?;
+        }
+
+        let progress = self.progress_ms.unwrap_or(0);
+        let total = self.duration_ms.unwrap_or(0);
+
+        writeln!(
+            out,
+            "{} {} / {} {}",
+            progress_bar(progress, total),
+            duration(progress),
+            duration(total),
+            if self.state == PlayerState::Playing {
+                "[playing]"
+            } else {
+                "[paused]"
+            },
+        )?;
+
+        write!(
+            out,
+            "shuffle: {}  repeat: {}",
+            if self.shuffle == Some(true) {
+                "on"
+            } else {
+                "off"
+            },
+            self.repeat.as_deref().unwrap_or("off"),
+        )?;
+
+        if let Some(device) = &self.device {
+            write!(out, "  device: {}", device)?;
+
+            if let Some(kind) = &self.device_kind {
+                write!(out, " ({})", kind)?;
+            }
+        }
+
+        Ok(writeln!(out)?)
+    }
+}
+
+/// A search result, `index` can be passed to commands taking a uri to refer to this result.
+#[derive(Serialize, Deserialize)]
+pub struct SearchRecord {
+    pub index: u32,
+
+    #[serde(flatten)]
+    pub item: model::ItemSummary,
+}
+
+impl Record for SearchRecord {
+    const FIELDS: &'static [&'static str] = &["index", "type", "name", "by", "uri"];
+
+    fn human(&self, out: &mut dyn Write) -> Result<()> {
+        write!(
+            out,
+            "{:>3}. {:<9}{}",
+            self.index,
+            self.item.kind.as_str(),
+            self.item.name
+        )?;
+
+        if let Some(by) = &self.item.by {
+            write!(out, " - {}", by)?;
+        }
+
+        Ok(writeln!(out)?)
+    }
+}
+
+/// An item in the queue, position 0 is the currently playing item.
+#[derive(Serialize)]
+pub struct QueueRecord {
+    pub position: u32,
+
+    #[serde(flatten)]
+    pub item: model::ItemSummary,
+}
+
+impl Record for QueueRecord {
+    const FIELDS: &'static [&'static str] = &["position", "type", "name", "by", "uri"];
+
+    fn human(&self, out: &mut dyn Write) -> Result<()> {
+        if self.position == 0 {
+            write!(out, "now: {}", self.item.name)?;
+        } else {
+            write!(out, "{:>3}. {}", self.position, self.item.name)?;
+        }
+
+        if let Some(by) = &self.item.by {
+            write!(out, " - {}", by)?;
+        }
+
+        Ok(writeln!(out)?)
+    }
+}
+
+/// An item of the library or of a playlist.
+#[derive(Serialize)]
+pub struct AddedRecord {
+    pub index: u32,
+    pub added_at: Option<chrono::DateTime<chrono::Utc>>,
+
+    #[serde(flatten)]
+    pub item: model::ItemSummary,
+}
+
+impl Record for AddedRecord {
+    const FIELDS: &'static [&'static str] = &["index", "added_at", "type", "name", "by", "uri"];
+
+    fn human(&self, out: &mut dyn Write) -> Result<()> {
+        write!(
+            out,
+            "{:>3}. {:<10} {}",
+            self.index,
+            self.added_at
+                .map(|at| at.format("%Y-%m-%d").to_string())
+                .unwrap_or_default(),
+            self.item.name
+        )?;
+
+        if let Some(by) = &self.item.by {
+            write!(out, " - {}", by)?;
+        }
+
+        Ok(writeln!(out)?)
+    }
+}
+
+#[derive(Serialize)]
+pub struct SavedRecord {
+    pub uri: String,
+    pub saved: bool,
+}
+
+impl Record for SavedRecord {
+    const FIELDS: &'static [&'static str] = &["uri", "saved"];
+
+    fn human(&self, out: &mut dyn Write) -> Result<()> {
+        Ok(writeln!(
+            out,
+            "{} is {}in the library",
+            self.uri,
+            if self.saved { "" } else { "not " }
+        )?)
+    }
+}
+
+#[derive(Serialize)]
+pub struct PlaylistRecord {
+    pub index: u32,
+    pub id: String,
+    pub name: String,
+    pub owner: String,
+    pub tracks: u32,
+    pub uri: String,
+}
+
+impl PlaylistRecord {
+    pub fn new(index: u32, playlist: model::SimplePlaylist) -> Self {
+        Self {
+            index,
+            owner: playlist.owner.display_name.unwrap_or(playlist.owner.id),
+            id: playlist.id,
+            name: playlist.name,
+            tracks: playlist.tracks.total,
+            uri: playlist.uri,
+        }
+    }
+}
+
+impl Record for PlaylistRecord {
+    const FIELDS: &'static [&'static str] = &["index", "id", "name", "owner", "tracks", "uri"];
+
+    fn human(&self, out: &mut dyn Write) -> Result<()> {
+        Ok(writeln!(
+            out,
+            "{:>3}. {:<23}{} - {} ({} tracks)",
+            self.index, self.id, self.name, self.owner, self.tracks
+        )?)
+    }
+}
+
+/// A played track, `played_at` is in UTC and `context` is the uri of the album, playlist or
+/// artist the track was played from.
+#[derive(Serialize)]
+pub struct HistoryRecord {
+    pub played_at: chrono::DateTime<chrono::Utc>,
+    pub artist: String,
+    pub title: String,
+    pub album: String,
+    pub uri: String,
+    pub context: Option<String>,
+}
+
+impl From<model::PlayHistory> for HistoryRecord {
+    fn from(history: model::PlayHistory) -> Self {
+        Self {
+            played_at: history.played_at,
+            artist: model::artist_names(&history.track.artists),
+            title: history.track.name,
+            album: history.track.album.name,
+            uri: history.track.uri,
+            context: history.context.map(|context| context.uri),
+        }
+    }
+}
+
+impl Record for HistoryRecord {
+    const FIELDS: &'static [&'static str] =
+        &["played_at", "artist", "title", "album", "uri", "context"];
+
+    fn human(&self, out: &mut dyn Write) -> Result<()> {
+        Ok(writeln!(
+            out,
+            "{} {} - {}",
+            self.played_at
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M"),
+            self.artist,
+            self.title
+        )?)
+    }
+}
+
+#[derive(Serialize)]
+pub struct TopRecord {
+    pub rank: u32,
+
+    #[serde(flatten)]
+    pub item: model::ItemSummary,
+}
+
+impl Record for TopRecord {
+    const FIELDS: &'static [&'static str] = &["rank", "type", "name", "by", "uri"];
+
+    fn human(&self, out: &mut dyn Write) -> Result<()> {
+        write!(out, "{:>3}. {}", self.rank, self.item.name)?;
+
+        if let Some(by) = &self.item.by {
+            write!(out, " - {}", by)?;
+        }
+
+        Ok(writeln!(out)?)
+    }
+}
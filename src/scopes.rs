@@ -0,0 +1,90 @@
+//! Authorization scopes, see https://developer.spotify.com/documentation/general/guides/scopes/
+//!
+//! Every command declares the scopes it needs and spotr only asks for those, the scopes granted
+//! to a client are stored with its token and extended when a command needs more.
+
+pub const USER_READ_CURRENTLY_PLAYING: &str = "user-read-currently-playing";
+pub const USER_READ_PLAYBACK_STATE: &str = "user-read-playback-state";
+pub const USER_MODIFY_PLAYBACK_STATE: &str = "user-modify-playback-state";
+pub const USER_LIBRARY_READ: &str = "user-library-read";
+pub const USER_LIBRARY_MODIFY: &str = "user-library-modify";
+pub const PLAYLIST_READ_PRIVATE: &str = "playlist-read-private";
+pub const PLAYLIST_READ_COLLABORATIVE: &str = "playlist-read-collaborative";
+pub const PLAYLIST_MODIFY_PUBLIC: &str = "playlist-modify-public";
+pub const PLAYLIST_MODIFY_PRIVATE: &str = "playlist-modify-private";
+pub const USER_READ_RECENTLY_PLAYED: &str = "user-read-recently-played";
+pub const USER_TOP_READ: &str = "user-top-read";
+pub const USER_READ_PRIVATE: &str = "user-read-private";
+
+/// Reading what is playing and on which device.
+pub const PLAYBACK_READ: &[&str] = &[USER_READ_PLAYBACK_STATE, USER_READ_CURRENTLY_PLAYING];
+
+/// Controlling playback, which also involves reading the playback state and devices.
+pub const PLAYBACK: &[&str] = &[
+    USER_READ_PLAYBACK_STATE,
+    USER_READ_CURRENTLY_PLAYING,
+    USER_MODIFY_PLAYBACK_STATE,
+];
+
+/// Reading playlists, including private and collaborative ones.
+pub const PLAYLIST_READ: &[&str] = &[PLAYLIST_READ_PRIVATE, PLAYLIST_READ_COLLABORATIVE];
+
+/// Editing playlists, which are looked up by name among the user's playlists and may be given
+/// the currently playing item.
+pub const PLAYLIST_MODIFY: &[&str] = &[
+    PLAYLIST_READ_PRIVATE,
+    PLAYLIST_READ_COLLABORATIVE,
+    PLAYLIST_MODIFY_PUBLIC,
+    PLAYLIST_MODIFY_PRIVATE,
+    USER_READ_PLAYBACK_STATE,
+    USER_READ_CURRENTLY_PLAYING,
+];
+
+/// Saving and removing items, by default the currently playing one.
+pub const LIBRARY_MODIFY: &[&str] = &[
+    USER_LIBRARY_MODIFY,
+    USER_READ_PLAYBACK_STATE,
+    USER_READ_CURRENTLY_PLAYING,
+];
+
+/// Every scope some command needs, requested by `spotr login`.
+pub const ALL: &[&str] = &[
+    USER_READ_CURRENTLY_PLAYING,
+    USER_READ_PLAYBACK_STATE,
+    USER_MODIFY_PLAYBACK_STATE,
+    USER_LIBRARY_READ,
+    USER_LIBRARY_MODIFY,
+    PLAYLIST_READ_PRIVATE,
+    PLAYLIST_READ_COLLABORATIVE,
+    PLAYLIST_MODIFY_PUBLIC,
+    PLAYLIST_MODIFY_PRIVATE,
+    USER_READ_RECENTLY_PLAYED,
+    USER_TOP_READ,
+    USER_READ_PRIVATE,
+];
+
+/// Scopes granted to tokens stored before the granted scopes were recorded, which only the
+/// release before then wrote.
+pub fn legacy() -> Vec<String> {
+    vec![
+        USER_READ_CURRENTLY_PLAYING.to_owned(),
+        USER_MODIFY_PLAYBACK_STATE.to_owned(),
+    ]
+}
+
+/// Scopes in `required` that are not in `granted`.
+pub fn missing<'a>(granted: &[String], required: &[&'a str]) -> Vec<&'a str> {
+    required
+        .iter()
+        .copied()
+        .filter(|&scope| !granted.iter().any(|granted| granted == scope))
+        .collect()
+}
+
+/// `granted` extended with the scopes of `requested` it lacks.
+pub fn union(granted: &[String], requested: &[&str]) -> Vec<String> {
+    let mut scopes = granted.to_vec();
+    scopes.extend(missing(granted, requested).into_iter().map(str::to_owned));
+
+    scopes
+}